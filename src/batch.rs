@@ -0,0 +1,103 @@
+use log::info;
+use std::sync::atomic::{AtomicBool, Ordering};
+use swayipc::{Connection as SwayConnection, Fallible};
+
+use crate::error::InputdError;
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Makes batches log their commands instead of sending them to sway.
+pub fn set_dry_run(enabled: bool) {
+    DRY_RUN.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Commands collected during one apply cycle, sent to sway in a single
+/// `run_command` round-trip.
+#[derive(Default)]
+pub struct CommandBatch {
+    commands: Vec<(&'static str, String, String)>,
+}
+
+/// Outcome of one command of a batch, with the gsettings key it came from.
+#[derive(Debug)]
+pub struct CommandResult {
+    pub key: &'static str,
+    pub value: String,
+    pub command: String,
+    pub outcome: Result<(), InputdError>,
+}
+
+impl CommandBatch {
+    pub fn new() -> CommandBatch {
+        CommandBatch::default()
+    }
+
+    pub fn push(&mut self, key: &'static str, value: String, command: String) {
+        self.commands.push((key, value, command));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &str> {
+        self.commands.iter().map(|(_, _, command)| command.as_str())
+    }
+
+    pub fn payload(&self) -> String {
+        self.commands
+            .iter()
+            .map(|(_, _, command)| command.as_str())
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    /// Runs every command at once and maps each outcome back to the key that
+    /// produced the command.
+    pub fn run(self, connection: &mut SwayConnection) -> Fallible<Vec<CommandResult>> {
+        if is_dry_run() {
+            return Ok(self.skip());
+        }
+        let mut outcomes = connection.run_command(self.payload())?.into_iter();
+        Ok(self
+            .commands
+            .into_iter()
+            .map(|(key, value, command)| {
+                let outcome = match outcomes.next() {
+                    Some(outcome) => outcome
+                        .map(|_| ())
+                        .map_err(|e| InputdError::CommandRejected(e.to_string())),
+                    None => Err(InputdError::CommandRejected(String::from(
+                        "sway returned no outcome for this command",
+                    ))),
+                };
+                CommandResult {
+                    key,
+                    value,
+                    command,
+                    outcome,
+                }
+            })
+            .collect())
+    }
+
+    /// Logs every command as if it had succeeded.
+    fn skip(self) -> Vec<CommandResult> {
+        self.commands
+            .into_iter()
+            .map(|(key, value, command)| {
+                info!("Dry run, not sending: {command}");
+                CommandResult {
+                    key,
+                    value,
+                    command,
+                    outcome: Ok(()),
+                }
+            })
+            .collect()
+    }
+}
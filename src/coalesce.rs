@@ -0,0 +1,35 @@
+/// Changes waiting for the end of a coalescing window. Only the latest value
+/// pushed for a key is kept, in the order of each key's latest change.
+pub struct Coalescer<K, V> {
+    pending: Vec<(K, V)>,
+    scheduled: bool,
+}
+
+impl<K: PartialEq, V> Coalescer<K, V> {
+    pub fn new() -> Coalescer<K, V> {
+        Coalescer {
+            pending: Vec::new(),
+            scheduled: false,
+        }
+    }
+
+    /// Stores `value` for `key`. Returns true if this opened a new window,
+    /// in which case the caller has to schedule a flush.
+    pub fn push(&mut self, key: K, value: V) -> bool {
+        self.pending.retain(|(k, _)| *k != key);
+        self.pending.push((key, value));
+        !std::mem::replace(&mut self.scheduled, true)
+    }
+
+    /// Takes every pending change and closes the window.
+    pub fn take(&mut self) -> Vec<(K, V)> {
+        self.scheduled = false;
+        std::mem::take(&mut self.pending)
+    }
+}
+
+impl<K: PartialEq, V> Default for Coalescer<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
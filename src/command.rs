@@ -0,0 +1,315 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use crate::state::PropertyValue;
+
+const INPUT_TYPES: &[&str] = &[
+    "keyboard",
+    "pointer",
+    "touchpad",
+    "touch",
+    "tablet_tool",
+    "tablet_pad",
+    "switch",
+];
+
+pub const INPUT_PROPERTIES: &[&str] = &[
+    "accel_profile",
+    "click_method",
+    "drag",
+    "drag_lock",
+    "dwt",
+    "events",
+    "left_handed",
+    "middle_emulation",
+    "natural_scroll",
+    "pointer_accel",
+    "repeat_delay",
+    "repeat_rate",
+    "scroll_button",
+    "scroll_factor",
+    "scroll_method",
+    "tap",
+    "tap_button_map",
+    "xkb_layout",
+    "xkb_options",
+    "xkb_switch_layout",
+    "xkb_variant",
+];
+
+/// A part of a command that was rejected before reaching sway.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCommand {
+    what: &'static str,
+    value: String,
+}
+
+impl Display for InvalidCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid {}: {:?}", self.what, self.value)
+    }
+}
+
+impl Error for InvalidCommand {}
+
+/// Devices an `input` command applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputTarget {
+    Type(String),
+    Identifier(String),
+}
+
+/// An `input <target> <property> <argument>` command. Every part is either
+/// checked against what sway knows or quoted, so a gsettings value can't
+/// end the command and start another one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputCommand {
+    target: InputTarget,
+    property: &'static str,
+    argument: PropertyValue,
+}
+
+impl InputCommand {
+    pub fn new(
+        target: InputTarget,
+        property: &'static str,
+        argument: &PropertyValue,
+    ) -> Result<InputCommand, InvalidCommand> {
+        match &target {
+            InputTarget::Type(input_type) if !INPUT_TYPES.contains(&input_type.as_str()) => {
+                return Err(invalid("input type", input_type));
+            }
+            InputTarget::Identifier(identifier) if identifier.is_empty() => {
+                return Err(invalid("input identifier", identifier));
+            }
+            InputTarget::Identifier(identifier) => check_printable("input identifier", identifier)?,
+            InputTarget::Type(_) => (),
+        }
+        if !INPUT_PROPERTIES.contains(&property) {
+            return Err(invalid("input property", property));
+        }
+        match argument {
+            PropertyValue::Number(n) if !n.is_finite() => {
+                return Err(invalid("number", &n.to_string()));
+            }
+            PropertyValue::Word(word) => check_printable("argument", word)?,
+            _ => (),
+        }
+        Ok(InputCommand {
+            target,
+            property,
+            argument: argument.clone(),
+        })
+    }
+
+    pub fn target(&self) -> &InputTarget {
+        &self.target
+    }
+
+    /// The `<property> <argument>` part, as written inside an `input` block.
+    pub fn setting(&self) -> String {
+        match &self.argument {
+            PropertyValue::Word(word) => format!("{} {}", self.property, quote(word)),
+            argument => format!("{} {argument}", self.property),
+        }
+    }
+}
+
+impl Display for InputTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputTarget::Type(input_type) => write!(f, "type:{input_type}"),
+            InputTarget::Identifier(identifier) => write!(f, "{}", quote(identifier)),
+        }
+    }
+}
+
+impl Display for InputCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "input {} {}", self.target, self.setting())
+    }
+}
+
+fn invalid(what: &'static str, value: &str) -> InvalidCommand {
+    InvalidCommand {
+        what,
+        value: value.to_owned(),
+    }
+}
+
+/// Control characters would end the command in a config file, and sway
+/// can't escape them.
+fn check_printable(what: &'static str, value: &str) -> Result<(), InvalidCommand> {
+    if value.chars().any(char::is_control) {
+        return Err(invalid(what, value));
+    }
+    Ok(())
+}
+
+/// Double-quotes `arg` and escapes the characters sway's argument splitter
+/// treats specially inside quotes.
+pub fn quote(arg: &str) -> String {
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    for c in arg.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTILE: &[&str] = &[
+        "\"",
+        "\\",
+        ";",
+        ",",
+        "\\\"",
+        "\"; exec rm -rf ~",
+        "exec swaynag -m pwned",
+        ", exec foo",
+        "us\\",
+        "}",
+        "$mod",
+        " ",
+        "",
+    ];
+
+    /// Every concatenation of up to three hostile fragments.
+    fn hostile_values() -> Vec<String> {
+        let mut values: Vec<String> = HOSTILE.iter().map(|s| s.to_string()).collect();
+        for a in HOSTILE {
+            for b in HOSTILE {
+                values.push(format!("{a}{b}"));
+                for c in HOSTILE {
+                    values.push(format!("{a}x{b}y{c}"));
+                }
+            }
+        }
+        values
+    }
+
+    /// Splits `command` into commands and their arguments the way sway
+    /// does: `;` and `,` separate commands outside of quotes, and a
+    /// backslash escapes the next character inside them.
+    fn split(command: &str) -> Vec<Vec<String>> {
+        let mut commands = vec![Vec::new()];
+        let mut word: Option<String> = None;
+        let mut chars = command.chars();
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if quoted => {
+                    let escaped = chars.next().expect("dangling escape");
+                    word.get_or_insert_with(String::new).push(escaped);
+                }
+                '"' => {
+                    quoted = !quoted;
+                    word.get_or_insert_with(String::new);
+                }
+                ';' | ',' if !quoted => {
+                    commands.last_mut().unwrap().extend(word.take());
+                    commands.push(Vec::new());
+                }
+                ' ' if !quoted => commands.last_mut().unwrap().extend(word.take()),
+                c => word.get_or_insert_with(String::new).push(c),
+            }
+        }
+        assert!(!quoted, "unterminated quote in {command}");
+        commands.last_mut().unwrap().extend(word);
+        commands
+    }
+
+    fn keyboards() -> InputTarget {
+        InputTarget::Type(String::from("keyboard"))
+    }
+
+    #[test]
+    fn hostile_arguments_stay_one_argument() {
+        for value in hostile_values() {
+            let argument = PropertyValue::Word(value.clone());
+            let command = InputCommand::new(keyboards(), "xkb_layout", &argument).unwrap();
+            let rendered = command.to_string();
+            assert_eq!(
+                split(&rendered),
+                vec![vec!["input", "type:keyboard", "xkb_layout", value.as_str()]],
+                "{rendered}"
+            );
+        }
+    }
+
+    #[test]
+    fn hostile_identifiers_stay_one_target() {
+        for value in hostile_values().into_iter().filter(|v| !v.is_empty()) {
+            let target = InputTarget::Identifier(value.clone());
+            let command = InputCommand::new(target, "tap", &PropertyValue::Toggle(true)).unwrap();
+            let rendered = command.to_string();
+            assert_eq!(
+                split(&rendered),
+                vec![vec!["input", value.as_str(), "tap", "enabled"]],
+                "{rendered}"
+            );
+        }
+    }
+
+    #[test]
+    fn control_characters_are_rejected() {
+        for value in ["us\nexec foo", "us\rexec foo", "\0", "us\tde", "\u{1b}[2J"] {
+            let argument = PropertyValue::Word(String::from(value));
+            assert!(InputCommand::new(keyboards(), "xkb_layout", &argument).is_err());
+            let target = InputTarget::Identifier(String::from(value));
+            assert!(InputCommand::new(target, "tap", &PropertyValue::Toggle(true)).is_err());
+        }
+    }
+
+    #[test]
+    fn unknown_types_are_rejected() {
+        for input_type in [
+            "",
+            "keyboards",
+            "keyboard; exec foo",
+            "keyboard tap enabled,",
+            "*",
+        ] {
+            let target = InputTarget::Type(String::from(input_type));
+            assert!(InputCommand::new(target, "tap", &PropertyValue::Toggle(true)).is_err());
+        }
+    }
+
+    #[test]
+    fn empty_identifiers_are_rejected() {
+        let target = InputTarget::Identifier(String::new());
+        assert!(InputCommand::new(target, "tap", &PropertyValue::Toggle(true)).is_err());
+    }
+
+    #[test]
+    fn unknown_properties_are_rejected() {
+        for property in ["", "exec", "tap; exec foo", "map_to_output", "TAP"] {
+            assert!(
+                InputCommand::new(keyboards(), property, &PropertyValue::Toggle(true)).is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn non_finite_numbers_are_rejected() {
+        for n in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let argument = PropertyValue::Number(n);
+            assert!(InputCommand::new(keyboards(), "repeat_rate", &argument).is_err());
+        }
+    }
+
+    #[test]
+    fn quote_escapes_quotes_and_backslashes() {
+        assert_eq!(quote("us"), "\"us\"");
+        assert_eq!(quote("a\"b"), "\"a\\\"b\"");
+        assert_eq!(quote("a\\b"), "\"a\\\\b\"");
+        assert_eq!(quote(";,"), "\";,\"");
+    }
+}
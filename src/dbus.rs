@@ -0,0 +1,165 @@
+use gio::{DBusConnection, DBusMethodInvocation, DBusNodeInfo, RegistrationId};
+use glib::thread_guard::ThreadGuard;
+use glib::{Sender, ToVariant, Variant};
+use log::error;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use swayipc::Input;
+
+use crate::command::INPUT_PROPERTIES;
+use crate::error::{InputdError, Result};
+use crate::layout::ActiveSource;
+use crate::state::observed_property;
+use crate::Message;
+
+pub const OBJECT_PATH: &str = "/org/regolith/inputd";
+pub const INTERFACE: &str = "org.regolith.inputd";
+const ERROR_NAME: &str = "org.regolith.inputd.Error.Failed";
+
+const INTERFACE_XML: &str = r#"
+<node>
+  <interface name="org.regolith.inputd">
+    <property name="ActiveSource" type="(ssus)" access="read">
+      <annotation name="org.freedesktop.DBus.Property.EmitsChangedSignal" value="true"/>
+    </property>
+    <method name="ReapplyAll"/>
+    <method name="ListDevices">
+      <arg type="a(sssa{ss})" name="devices" direction="out"/>
+    </method>
+    <method name="SetDeviceProperty">
+      <arg type="s" name="identifier" direction="in"/>
+      <arg type="s" name="property" direction="in"/>
+      <arg type="s" name="value" direction="in"/>
+    </method>
+    <signal name="DeviceAdded">
+      <arg type="s" name="identifier"/>
+      <arg type="s" name="type"/>
+    </signal>
+    <signal name="DeviceRemoved">
+      <arg type="s" name="identifier"/>
+      <arg type="s" name="type"/>
+    </signal>
+    <signal name="LayoutChanged">
+      <arg type="s" name="identifier"/>
+      <arg type="s" name="layout"/>
+    </signal>
+  </interface>
+</node>
+"#;
+
+/// A method call of the interface, answered by the manager on the main
+/// context.
+pub struct DBusCall {
+    pub method: String,
+    pub parameters: Variant,
+    invocation: ThreadGuard<DBusMethodInvocation>,
+}
+
+impl DBusCall {
+    pub fn reply(self, result: Result<Option<Variant>>) {
+        let invocation = self.invocation.into_inner();
+        match result {
+            Ok(value) => invocation.return_value(value.as_ref()),
+            Err(e) => invocation.return_dbus_error(ERROR_NAME, &e.to_string()),
+        }
+    }
+}
+
+/// The org.regolith.inputd interface, exported on the connection of the
+/// application owning the bus name.
+pub struct DBusService {
+    connection: DBusConnection,
+    active_source: Arc<Mutex<Variant>>,
+    _registration: RegistrationId,
+}
+
+impl DBusService {
+    /// Exports the interface, forwarding its method calls to `sender`.
+    pub fn register(connection: DBusConnection, sender: Sender<Message>) -> Result<DBusService> {
+        let node = DBusNodeInfo::for_xml(INTERFACE_XML).map_err(InputdError::DBus)?;
+        let interface = node
+            .lookup_interface(INTERFACE)
+            .expect("Interface is part of the introspection data");
+        let sender = Mutex::new(sender);
+        let active_source = Arc::new(Mutex::new(ActiveSource::default().dbus_value()));
+        let property = active_source.clone();
+        let registration = connection
+            .register_object(
+                OBJECT_PATH,
+                &interface,
+                move |_, _, _, _, method, parameters, invocation| {
+                    let call = DBusCall {
+                        method: method.to_owned(),
+                        parameters,
+                        invocation: ThreadGuard::new(invocation),
+                    };
+                    let sender = sender.lock().expect("D-Bus sender lock poisoned");
+                    if let Err(e) = sender.send(Message::DBusCall(call)) {
+                        error!("{e}");
+                    }
+                },
+                move |_, _, _, _, name| match name {
+                    "ActiveSource" => property.lock().expect("Property lock poisoned").clone(),
+                    _ => ().to_variant(),
+                },
+                |_, _, _, _, _, _| false,
+            )
+            .map_err(InputdError::DBus)?;
+        Ok(DBusService {
+            connection,
+            active_source,
+            _registration: registration,
+        })
+    }
+
+    /// Updates the `ActiveSource` property and notifies its watchers.
+    pub fn set_active_source(&self, source: &ActiveSource) {
+        let value = source.dbus_value();
+        *self.active_source.lock().expect("Property lock poisoned") = value.clone();
+        let changed = HashMap::from([(String::from("ActiveSource"), value)]);
+        let parameters = (INTERFACE, changed, Vec::<String>::new()).to_variant();
+        let result = self.connection.emit_signal(
+            None,
+            OBJECT_PATH,
+            "org.freedesktop.DBus.Properties",
+            "PropertiesChanged",
+            Some(&parameters),
+        );
+        if let Err(e) = result {
+            error!("{}", InputdError::DBus(e));
+        }
+    }
+
+    pub fn emit(&self, signal: &str, parameters: Variant) {
+        let result =
+            self.connection
+                .emit_signal(None, OBJECT_PATH, INTERFACE, signal, Some(&parameters));
+        if let Err(e) = result {
+            error!("{}", InputdError::DBus(e));
+        }
+    }
+}
+
+/// `ListDevices` entry of `input`: identifier, type, name, and the
+/// settings sway reports for it.
+pub fn device_entry(input: &Input) -> (String, String, String, HashMap<String, String>) {
+    let mut settings: HashMap<String, String> = INPUT_PROPERTIES
+        .iter()
+        .filter_map(|property| {
+            let value = observed_property(input, property)?;
+            Some((property.to_string(), value.to_string()))
+        })
+        .collect();
+    if !input.xkb_layout_names.is_empty() {
+        settings.insert(String::from("xkb_layout"), input.xkb_layout_names.join(","));
+    }
+    if let Some(layout) = &input.xkb_active_layout_name {
+        settings.insert(String::from("xkb_active_layout"), layout.clone());
+    }
+    (
+        input.identifier.clone(),
+        input.input_type.clone(),
+        input.name.clone(),
+        settings,
+    )
+}
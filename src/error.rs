@@ -0,0 +1,80 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use crate::command::InvalidCommand;
+
+/// Errors of the daemon, split by what the caller can do about them.
+#[derive(Debug)]
+pub enum InputdError {
+    /// Talking to sway failed. The connection may have to be re-established.
+    Ipc(swayipc::Error),
+    /// Sway understood the command but refused to apply it.
+    CommandRejected(String),
+    /// A gsettings schema or key is missing, or a value couldn't be written.
+    Settings(String),
+    /// A value can't be turned into a sway command.
+    Validation(String),
+    /// The compositor or the installed schemas don't support a feature.
+    Unsupported(String),
+    /// Exporting the D-Bus interface, emitting one of its signals or calling
+    /// a system service failed.
+    DBus(glib::Error),
+}
+
+pub type Result<T> = std::result::Result<T, InputdError>;
+
+impl InputdError {
+    /// Errors that may go away by reconnecting and trying again.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, InputdError::Ipc(_))
+    }
+
+    /// Errors caused by the user's settings, worth showing to them.
+    pub fn concerns_user(&self) -> bool {
+        matches!(
+            self,
+            InputdError::CommandRejected(_) | InputdError::Unsupported(_)
+        )
+    }
+}
+
+impl Display for InputdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputdError::Ipc(e) => write!(f, "Sway IPC error: {e}"),
+            InputdError::CommandRejected(reason) => write!(f, "Rejected by sway: {reason}"),
+            InputdError::Settings(reason) => write!(f, "GSettings error: {reason}"),
+            InputdError::Validation(reason) => f.write_str(reason),
+            InputdError::Unsupported(reason) => write!(f, "Unsupported: {reason}"),
+            InputdError::DBus(e) => write!(f, "D-Bus error: {e}"),
+        }
+    }
+}
+
+impl Error for InputdError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InputdError::Ipc(e) => Some(e),
+            InputdError::DBus(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<swayipc::Error> for InputdError {
+    fn from(e: swayipc::Error) -> Self {
+        InputdError::Ipc(e)
+    }
+}
+
+impl From<glib::BoolError> for InputdError {
+    fn from(e: glib::BoolError) -> Self {
+        InputdError::Settings(e.to_string())
+    }
+}
+
+impl From<InvalidCommand> for InputdError {
+    fn from(e: InvalidCommand) -> Self {
+        InputdError::Validation(e.to_string())
+    }
+}
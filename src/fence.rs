@@ -0,0 +1,70 @@
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::process;
+use swayipc::Connection as SwayConnection;
+
+/// Payload of the tick sent after every batch of sway commands.
+#[derive(Serialize, Deserialize, Debug)]
+struct FenceTick {
+    inputd_fence: u64,
+    pid: u32,
+}
+
+/// Tracks the ticks sent after each batch of sway commands. Input events are
+/// caused by our own commands until the last tick we sent comes back on the
+/// event stream, since sway delivers events in the order they happened.
+pub struct TickFence {
+    raised: u64,
+    lowered: u64,
+}
+
+impl TickFence {
+    pub fn new() -> TickFence {
+        TickFence {
+            raised: 0,
+            lowered: 0,
+        }
+    }
+
+    /// Sends a uniquely tagged tick behind the commands already run.
+    pub fn raise(&mut self, connection: &mut SwayConnection) {
+        let tick = FenceTick {
+            inputd_fence: self.raised + 1,
+            pid: process::id(),
+        };
+        let payload = serde_json::to_string(&tick).expect("Serializable fence tick");
+        match connection.send_tick(payload) {
+            Ok(_) => {
+                self.raised = tick.inputd_fence;
+                debug!("Raised fence {}", self.raised);
+            }
+            Err(e) => warn!("Failed to send fence tick: {e}"),
+        }
+    }
+
+    /// Returns true if input events are still caused by our own commands.
+    pub fn is_raised(&self) -> bool {
+        self.lowered < self.raised
+    }
+
+    /// Consumes a tick payload, returning true if it was one of our fences.
+    pub fn try_lower(&mut self, payload: &str) -> bool {
+        match serde_json::from_str::<FenceTick>(payload) {
+            Ok(tick) if tick.pid == process::id() => {
+                self.lowered = self.lowered.max(tick.inputd_fence);
+                debug!("Lowered fence {}", tick.inputd_fence);
+                true
+            }
+            // Fences of other instances are not ours to track, but they are
+            // not reload notifications either
+            Ok(_) => true,
+            Err(_) => false,
+        }
+    }
+}
+
+impl Default for TickFence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,24 +1,83 @@
+use crate::batch::{CommandBatch, CommandResult};
+use crate::command::{InputCommand, InputTarget};
+use crate::error::{InputdError, Result};
+use crate::layout::ActiveSource;
+use crate::localed::SystemKeymap;
+use crate::state::{DesiredProperty, DesiredState, PropertyValue};
+use crate::status::STATUS_SCHEMA;
+use crate::sway_config::ConfigProperties;
+use crate::utils::{self, set_if_changed};
 use crate::InputHandler;
-use gio::{prelude::SettingsExtManual, Settings};
+use gio::{prelude::SettingsExtManual, traits::SettingsExt, Settings};
+use glib::{ToVariant, Variant};
 use log::info;
-use std::error::Error;
 use swayipc::Connection as SwayConnection;
 
 pub struct InputSourcesHandler {
     settings: Settings,
     sway_connection: SwayConnection,
+    active_source: Option<ActiveSource>,
+    /// Applied while `sources` is empty
+    system_keymap: Option<SystemKeymap>,
 }
 impl InputSourcesHandler {
-    pub fn new() -> InputSourcesHandler {
-        let settings = Settings::new("org.gnome.desktop.input-sources");
-        let sway_connection = SwayConnection::new().unwrap();
-        InputSourcesHandler {
+    pub fn new() -> Result<InputSourcesHandler> {
+        let settings = utils::installed_settings("org.gnome.desktop.input-sources")?;
+        let sway_connection = SwayConnection::new()?;
+        Ok(InputSourcesHandler {
             settings,
             sway_connection,
-        }
+            active_source: None,
+            system_keymap: SystemKeymap::read(),
+        })
+    }
+    /// Source at the layout index sway reports, as listed in `sources`.
+    fn active_source(&self, input: &swayipc::Input) -> Option<ActiveSource> {
+        let index = input.xkb_active_layout_index?;
+        let full_name = input.xkb_active_layout_name.clone().unwrap_or_default();
+        let sources: Vec<(String, String)> = self.settings().get("sources");
+        let (source_type, id) = match sources.into_iter().nth(index as usize) {
+            Some(source) => source,
+            None => (String::from("xkb"), full_name.clone()),
+        };
+        let short_name = id.split('+').next().unwrap_or_default().to_owned();
+        Some(ActiveSource {
+            short_name,
+            full_name,
+            index: index as u32,
+            source_type,
+        })
     }
-    fn apply_input_sources(&mut self) -> Result<(), Box<dyn Error>> {
+    fn input_sources(&self) -> Result<DesiredState> {
         let sources: Vec<(String, String)> = self.settings().get("sources");
+        if let (true, Some(keymap)) = (sources.is_empty(), &self.system_keymap) {
+            info!(
+                "No input sources, using the system keymap {}",
+                keymap.layout
+            );
+            let mut desired = vec![
+                DesiredProperty::new(
+                    "sources",
+                    "xkb_variant",
+                    PropertyValue::Word(keymap.variant.clone()),
+                ),
+                DesiredProperty::new(
+                    "sources",
+                    "xkb_layout",
+                    PropertyValue::Word(keymap.layout.clone()),
+                ),
+            ];
+            // Options set in gsettings still win over the system ones
+            let options: Vec<String> = self.settings().get("xkb-options");
+            if options.is_empty() && !keymap.options.is_empty() {
+                desired.push(DesiredProperty::new(
+                    "sources",
+                    "xkb_options",
+                    PropertyValue::Word(keymap.options.clone()),
+                ));
+            }
+            return Ok(desired);
+        }
         // Layout is of form code+variant
         let (layouts, variants) = sources
             .into_iter()
@@ -33,37 +92,218 @@ impl InputSourcesHandler {
             .reduce(|(layout, variant), (curr_layout, curr_variant)| {
                 (layout + "," + &curr_layout, variant + "," + &curr_variant)
             })
-            .ok_or("Invalid keyboard layout or variant")?;
-        let layout_cmd = format!("input type:keyboard xkb_layout '{layouts}'");
-        let vairants_cmd = format!("input type:keyboard xkb_variant '{variants}'");
-        info!("{vairants_cmd}");
-        info!("{layout_cmd}");
-        self.sway_connection().run_command(vairants_cmd)?;
-        self.sway_connection().run_command(layout_cmd)?;
-        Ok(())
+            .ok_or_else(|| {
+                InputdError::Validation(String::from("Invalid keyboard layout or variant"))
+            })?;
+        // The variant has to be set before the layout it belongs to
+        Ok(vec![
+            DesiredProperty::new("sources", "xkb_variant", PropertyValue::Word(variants)),
+            DesiredProperty::new("sources", "xkb_layout", PropertyValue::Word(layouts)),
+        ])
+    }
+    fn xkb_options(&self) -> DesiredProperty {
+        let options: Vec<String> = self.settings().get("xkb-options");
+        DesiredProperty::new(
+            "xkb-options",
+            "xkb_options",
+            PropertyValue::Word(options.join(",")),
+        )
     }
 }
 
 impl InputHandler for InputSourcesHandler {
-    fn apply_changes(&mut self, key: &str) -> Result<(), Box<dyn Error>> {
-        info!("org.gnome.desktop.input-sources -> Key: {key} chaged");
-        if key == "sources" {
-            self.apply_input_sources()?
-        };
-        Ok(())
+    fn input_type(&self) -> &str {
+        "keyboard"
     }
-    fn apply_all(&mut self) -> Result<(), Box<dyn Error>> {
-        self.apply_input_sources()
+    fn keys(&self) -> &[&'static str] {
+        &["sources", "xkb-options"]
+    }
+    fn desired_properties(&self, key: &str) -> Result<DesiredState> {
+        match key {
+            "sources" => self.input_sources(),
+            "xkb-options" => Ok(vec![self.xkb_options()]),
+            _ => Ok(vec![]),
+        }
     }
     fn settings(&self) -> &Settings {
         &self.settings
     }
-    fn sync_gsettings(&mut self, input: &swayipc::Input) -> Result<(), Box<dyn Error>> {
-        info!("xkb_layout: {}", input.xkb_layout_names[0]);
+    fn sync_gsettings(&mut self, input: &swayipc::Input) -> Result<()> {
+        let Some(layout) = input.xkb_layout_names.first() else {
+            return Ok(());
+        };
+        info!("xkb_layout: {layout}");
         Ok(())
     }
     fn sway_connection(&mut self) -> &mut swayipc::Connection {
         &mut self.sway_connection
     }
+    /// Makes the source at `index` of `sources` the active one in sway, and
+    /// records it in `current` and at the head of `mru-sources`.
+    fn select_source(&mut self, index: u32) -> Result<Vec<CommandResult>> {
+        let sources: Vec<(String, String)> = self.settings().get("sources");
+        let Some(source) = sources.get(index as usize).cloned() else {
+            return Ok(vec![]);
+        };
+        let results = self.switch_layout(index)?;
+
+        set_if_changed(self.settings(), "current", index)?;
+        if self.has_key("mru-sources") {
+            let mut mru: Vec<(String, String)> = self.settings().get("mru-sources");
+            mru.retain(|used| *used != source);
+            mru.insert(0, source);
+            set_if_changed(self.settings(), "mru-sources", mru)?;
+        }
+        Ok(results)
+    }
+    fn connect_system(&self) {
+        if let Some(keymap) = &self.system_keymap {
+            keymap.seed(self.settings());
+        }
+        let push = utils::settings_if_installed(STATUS_SCHEMA)
+            .filter(|options| utils::has_key(options, "push-to-localed"))
+            .map_or(false, |options| options.get::<bool>("push-to-localed"));
+        if push {
+            self.settings()
+                .connect_changed(Some("sources"), |settings, key| {
+                    SystemKeymap::push_first_source(&settings.get::<Vec<(String, String)>>(key));
+                });
+        }
+    }
+    /// Makes the source at `index` of `sources` the active one in sway,
+    /// without recording it as used.
+    fn switch_layout(&mut self, index: u32) -> Result<Vec<CommandResult>> {
+        let count = self
+            .settings()
+            .get::<Vec<(String, String)>>("sources")
+            .len();
+        if index as usize >= count {
+            return Ok(vec![]);
+        }
+        let target = InputTarget::Type(String::from("keyboard"));
+        let layout = PropertyValue::Number(index.into());
+        let command = InputCommand::new(target, "xkb_switch_layout", &layout)?;
+        let mut batch = CommandBatch::new();
+        batch.push("current", index.to_string(), command.to_string());
+        info!("Executing command: {}", batch.payload());
+        Ok(batch.run(self.sway_connection())?)
+    }
+    fn current_source(&self) -> Option<u32> {
+        match &self.active_source {
+            Some(source) => Some(source.index),
+            None if self.has_key("current") => Some(self.settings().get::<u32>("current")),
+            None => Some(0),
+        }
+    }
+    fn switch_source(&mut self, backward: bool) -> Result<Vec<CommandResult>> {
+        let count = self
+            .settings()
+            .get::<Vec<(String, String)>>("sources")
+            .len() as u32;
+        if count == 0 {
+            return Ok(vec![]);
+        }
+        let current = self.current_source().unwrap_or_default();
+        let index = if backward {
+            (current + count - 1) % count
+        } else {
+            (current + 1) % count
+        };
+        self.select_source(index)
+    }
+    fn track_layout(&mut self, input: &swayipc::Input) -> Option<ActiveSource> {
+        let source = self.active_source(input)?;
+        if self.active_source.as_ref() == Some(&source) {
+            return None;
+        }
+        info!(
+            "Active input source: {} ({})",
+            source.short_name, source.full_name
+        );
+        self.active_source = Some(source.clone());
+        Some(source)
+    }
+    fn imported_settings(&self, properties: &ConfigProperties) -> Vec<(&'static str, Variant)> {
+        let mut imported = Vec::new();
+        if let Some(layouts) = properties.get("xkb_layout") {
+            let variants = properties.get("xkb_variant").map_or("", String::as_str);
+            imported.push(("sources", xkb_sources(layouts, variants).to_variant()));
+        }
+        if let Some(options) = properties.get("xkb_options") {
+            let options: Vec<&str> = options.split(',').filter(|o| !o.is_empty()).collect();
+            imported.push(("xkb-options", options.to_variant()));
+        }
+        imported
+    }
+}
+
+/// Input sources for comma-separated XKB `layouts` and their `variants`,
+/// joining each layout with its variant if it has one.
+pub fn xkb_sources(layouts: &str, variants: &str) -> Vec<(String, String)> {
+    let variants: Vec<&str> = variants.split(',').collect();
+    layouts
+        .split(',')
+        .enumerate()
+        .map(|(index, layout)| match variants.get(index) {
+            Some(variant) if !variant.is_empty() => {
+                (String::from("xkb"), format!("{layout}+{variant}"))
+            }
+            _ => (String::from("xkb"), layout.to_owned()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    const SCHEMA: &str = "org.gnome.desktop.input-sources";
+
+    fn xkb(id: &str) -> (String, String) {
+        (String::from("xkb"), String::from(id))
+    }
+
+    /// A keyboard as reported by `get_inputs`, with `layouts` configured.
+    fn keyboard(layouts: &[&str]) -> swayipc::Input {
+        serde_json::from_value(serde_json::json!({
+            "identifier": "1:1:AT_Translated_Set_2_keyboard",
+            "name": "AT Translated Set 2 keyboard",
+            "vendor": 1,
+            "product": 1,
+            "type": "keyboard",
+            "xkb_layout_names": layouts,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn keyboards_without_layouts_are_skipped() {
+        let installed = gio::SettingsSchemaSource::default()
+            .and_then(|source| source.lookup(SCHEMA, true))
+            .is_some();
+        if !installed {
+            return;
+        }
+        let backend = gio::memory_settings_backend_new();
+        let (stream, _) = UnixStream::pair().unwrap();
+        let mut handler = InputSourcesHandler {
+            settings: Settings::with_backend(SCHEMA, &backend),
+            sway_connection: SwayConnection::from(stream),
+            active_source: None,
+            system_keymap: None,
+        };
+        assert!(handler.sync_gsettings(&keyboard(&[])).is_ok());
+        assert!(handler.sync_gsettings(&keyboard(&["English (US)"])).is_ok());
+    }
+
+    #[test]
+    fn layouts_are_joined_with_their_variants() {
+        assert_eq!(
+            xkb_sources("us,de,fr", ",nodeadkeys"),
+            [xkb("us"), xkb("de+nodeadkeys"), xkb("fr")]
+        );
+        assert_eq!(xkb_sources("us", ""), [xkb("us")]);
+        assert_eq!(xkb_sources("us", "dvorak"), [xkb("us+dvorak")]);
+    }
 }
-unsafe impl Send for InputSourcesHandler {}
@@ -0,0 +1,207 @@
+use gio::{prelude::SettingsExtManual, traits::SettingsExt, Settings};
+use log::{error, info, warn};
+use swayipc::Connection as SwayConnection;
+
+use crate::batch::CommandBatch;
+use crate::error::Result;
+use crate::sway_config;
+use crate::utils;
+use crate::Message;
+
+const KEYBINDINGS_SCHEMA: &str = "org.gnome.desktop.wm.keybindings";
+
+/// Command of the bindings we register, reported back in binding events
+pub const FORWARD_COMMAND: &str = "nop regolith-inputd switch-input-source";
+pub const BACKWARD_COMMAND: &str = "nop regolith-inputd switch-input-source-backward";
+
+/// GNOME keybindings cycling input sources, registered as sway bindings
+/// whose events route back into the daemon.
+pub struct SourceKeybindings {
+    settings: Option<Settings>,
+    /// Combinations we bound, with the gsettings key they come from
+    bound: Vec<(&'static str, String)>,
+}
+
+impl SourceKeybindings {
+    pub fn new() -> SourceKeybindings {
+        let settings = utils::settings_if_installed(KEYBINDINGS_SCHEMA);
+        if settings.is_none() {
+            warn!(
+                "Schema {KEYBINDINGS_SCHEMA} is not installed, input sources have no keybindings"
+            );
+        }
+        SourceKeybindings {
+            settings,
+            bound: Vec::new(),
+        }
+    }
+
+    /// Replaces the bindings registered before with the current ones. Key
+    /// combinations the sway config binds itself are left alone.
+    pub fn register(&mut self, connection: &mut SwayConnection) -> Result<()> {
+        let Some(settings) = &self.settings else {
+            return Ok(());
+        };
+        let taken: Vec<String> = match sway_config::bound_keys(connection) {
+            Ok(combos) => combos.iter().map(|combo| normalize(combo)).collect(),
+            Err(e) => {
+                warn!("Failed to read the bindings of the sway config: {e}");
+                Vec::new()
+            }
+        };
+        let mut batch = CommandBatch::new();
+        for (key, combo) in self.bound.drain(..) {
+            let command = format!("unbindsym --no-repeat {combo}");
+            batch.push(key, combo, command);
+        }
+        for (key, command) in [
+            ("switch-input-source", FORWARD_COMMAND),
+            ("switch-input-source-backward", BACKWARD_COMMAND),
+        ] {
+            if !utils::has_key(settings, key) {
+                continue;
+            }
+            let accels: Vec<String> = settings.get(key);
+            for accel in accels.iter().filter(|accel| !accel.is_empty()) {
+                match to_bindsym(accel) {
+                    Some(combo) if taken.contains(&normalize(&combo)) => {
+                        info!("{combo} is bound in the sway config, not binding {key} to it");
+                    }
+                    Some(combo) => {
+                        let bindsym = format!("bindsym --no-repeat {combo} {command}");
+                        batch.push(key, combo.clone(), bindsym);
+                        self.bound.push((key, combo));
+                    }
+                    None => warn!("Can't translate the {key} keybinding {accel} for sway"),
+                }
+            }
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        info!("Executing command: {}", batch.payload());
+        for result in batch.run(connection)? {
+            if let Err(e) = result.outcome {
+                warn!("{}: {e}", result.command);
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets the registered bindings, which sway drops when reloading.
+    pub fn forget(&mut self) {
+        self.bound.clear();
+    }
+
+    pub fn monitor(&self, sender: glib::Sender<Message>) {
+        let Some(settings) = &self.settings else {
+            return;
+        };
+        settings.connect_changed(None, move |_, key| {
+            if key.starts_with("switch-input-source") {
+                if let Err(e) = sender.send(Message::KeybindingsChanged) {
+                    error!("{e}");
+                }
+            }
+        });
+    }
+}
+
+impl Default for SourceKeybindings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Translates a GTK accelerator like `<Shift><Super>space` to a sway key
+/// combination like `Shift+Mod4+space`.
+pub fn to_bindsym(accel: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    let mut rest = accel.trim();
+    while let Some(modifier) = rest.strip_prefix('<') {
+        let (name, after) = modifier.split_once('>')?;
+        let modifier = match name.to_ascii_lowercase().as_str() {
+            "shift" => "Shift",
+            "control" | "ctrl" | "ctl" | "primary" => "Control",
+            "alt" | "mod1" => "Mod1",
+            "super" | "mod4" => "Mod4",
+            "mod2" => "Mod2",
+            "mod3" | "hyper" => "Mod3",
+            "mod5" => "Mod5",
+            _ => return None,
+        };
+        if !parts.contains(&modifier) {
+            parts.push(modifier);
+        }
+        rest = after;
+    }
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    if rest.is_empty() || !rest.chars().all(valid) {
+        return None;
+    }
+    parts.push(rest);
+    Some(parts.join("+"))
+}
+
+/// Puts the modifiers of a sway key combination in a fixed order and
+/// spelling, so `$mod+Shift+space` and `Shift+Mod4+Space` compare equal.
+fn normalize(combo: &str) -> String {
+    let mut parts: Vec<String> = combo
+        .split('+')
+        .map(|part| match part.to_ascii_lowercase().as_str() {
+            "ctrl" | "control" => String::from("Control"),
+            "alt" | "mod1" => String::from("Mod1"),
+            "super" | "mod4" => String::from("Mod4"),
+            "shift" => String::from("Shift"),
+            "mod2" => String::from("Mod2"),
+            "mod3" => String::from("Mod3"),
+            "mod5" => String::from("Mod5"),
+            key => key.to_owned(),
+        })
+        .collect();
+    let key = parts.pop().unwrap_or_default();
+    parts.sort();
+    parts.dedup();
+    parts.push(key);
+    parts.join("+")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accelerators_translate_to_sway_combos() {
+        assert_eq!(to_bindsym("<Super>space").as_deref(), Some("Mod4+space"));
+        assert_eq!(
+            to_bindsym("<Shift><Super>space").as_deref(),
+            Some("Shift+Mod4+space")
+        );
+        assert_eq!(
+            to_bindsym("<Primary><Alt>Tab").as_deref(),
+            Some("Control+Mod1+Tab")
+        );
+        assert_eq!(to_bindsym("XF86Keyboard").as_deref(), Some("XF86Keyboard"));
+    }
+
+    #[test]
+    fn unknown_accelerators_are_not_translated() {
+        for accel in [
+            "",
+            "<Super>",
+            "<Meta>space",
+            "<Super>space; exec foo",
+            "<Super",
+        ] {
+            assert_eq!(to_bindsym(accel), None, "{accel}");
+        }
+    }
+
+    #[test]
+    fn equivalent_combos_normalize_equally() {
+        assert_eq!(normalize("Mod4+Shift+space"), normalize("Shift+Mod4+space"));
+        assert_eq!(normalize("Super+space"), normalize("Mod4+Space"));
+        assert_eq!(normalize("Ctrl+Alt+Tab"), normalize("Mod1+Control+Tab"));
+        assert_ne!(normalize("Mod4+space"), normalize("Mod4+Shift+space"));
+    }
+}
@@ -1,7 +1,10 @@
+use crate::error::Result;
+use crate::state::{DesiredProperty, DesiredState, PropertyValue};
+use crate::sway_config::ConfigProperties;
+use crate::utils;
 use crate::InputHandler;
 use gio::{prelude::SettingsExtManual, Settings};
-use log::info;
-use std::error::Error;
+use glib::{ToVariant, Variant};
 use swayipc::Connection as SwayConnection;
 
 pub struct KeyboardHandler {
@@ -9,38 +12,43 @@ pub struct KeyboardHandler {
     sway_connection: SwayConnection,
 }
 impl KeyboardHandler {
-    pub fn new() -> KeyboardHandler {
-        let settings = Settings::new("org.gnome.desktop.peripherals.keyboard");
-        let sway_connection = SwayConnection::new().unwrap();
-        KeyboardHandler {
+    pub fn new() -> Result<KeyboardHandler> {
+        let settings = utils::installed_settings("org.gnome.desktop.peripherals.keyboard")?;
+        let sway_connection = SwayConnection::new()?;
+        Ok(KeyboardHandler {
             settings,
             sway_connection,
-        }
+        })
     }
-    fn apply_repeat_interval(&mut self) -> Result<(), Box<dyn Error>> {
+    fn repeat_interval(&self) -> DesiredProperty {
         let interval: u32 = self.settings().get("repeat-interval");
-        let repeat_freq = 1000f64 / interval as f64;
-        let cmd = format!("input type:keyboard repeat_rate {repeat_freq}");
-        self.sway_connection().run_command(cmd)?;
-        Ok(())
+        // Sway only takes whole characters per second
+        let repeat_freq = (1000f64 / interval.max(1) as f64).round();
+        DesiredProperty::new(
+            "repeat-interval",
+            "repeat_rate",
+            PropertyValue::Number(repeat_freq),
+        )
     }
-    fn apply_repeat_delay(&mut self) -> Result<(), Box<dyn Error>> {
+    fn repeat_delay(&self) -> DesiredProperty {
         let delay: u32 = self.settings().get("delay");
-        let cmd = format!("input type:keyboard repeat_delay {delay}");
-        self.sway_connection().run_command(cmd)?;
-        Ok(())
+        DesiredProperty::new("delay", "repeat_delay", PropertyValue::Number(delay as f64))
     }
 }
 
 impl InputHandler for KeyboardHandler {
-    fn apply_changes(&mut self, key: &str) -> Result<(), Box<dyn Error>> {
-        info!("org.gnome.desktop.peripherals.keyboard -> Key: {key} chaged");
-        match key {
-            "repeat-interval" => self.apply_repeat_interval()?,
-            "delay" => self.apply_repeat_delay()?,
-            _ => (),
-        };
-        Ok(())
+    fn input_type(&self) -> &str {
+        "keyboard"
+    }
+    fn keys(&self) -> &[&'static str] {
+        &["repeat-interval", "delay"]
+    }
+    fn desired_properties(&self, key: &str) -> Result<DesiredState> {
+        Ok(match key {
+            "repeat-interval" => vec![self.repeat_interval()],
+            "delay" => vec![self.repeat_delay()],
+            _ => vec![],
+        })
     }
     fn settings(&self) -> &Settings {
         &self.settings
@@ -48,11 +56,25 @@ impl InputHandler for KeyboardHandler {
     fn sway_connection(&mut self) -> &mut swayipc::Connection {
         &mut self.sway_connection
     }
-    fn apply_all(&mut self) -> Result<(), Box<dyn Error>> {
+    fn sync_gsettings(&mut self, _: &swayipc::Input) -> Result<()> {
         Ok(())
     }
-    fn sync_gsettings(&mut self, _: &swayipc::Input) -> Result<(), Box<dyn Error>> {
-        Ok(())
+    fn imported_settings(&self, properties: &ConfigProperties) -> Vec<(&'static str, Variant)> {
+        let mut imported = Vec::new();
+        // Sway takes a rate in characters per second, gsettings an interval
+        let rate = properties
+            .get("repeat_rate")
+            .and_then(|rate| rate.parse::<f64>().ok());
+        if let Some(rate) = rate.filter(|rate| *rate > 0.0) {
+            let interval = (1000f64 / rate).round() as u32;
+            imported.push(("repeat-interval", interval.to_variant()));
+        }
+        if let Some(delay) = properties
+            .get("repeat_delay")
+            .and_then(|d| d.parse::<u32>().ok())
+        {
+            imported.push(("delay", delay.to_variant()));
+        }
+        imported
     }
 }
-unsafe impl Send for KeyboardHandler {}
@@ -0,0 +1,161 @@
+use glib::{Continue, IOCondition, SourceId, ToVariant, Variant};
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+const SOCKET_NAME: &str = "regolith-inputd-layout.sock";
+
+/// The input source a keyboard is using, as shown by layout indicators.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ActiveSource {
+    /// Layout code, like `de`
+    pub short_name: String,
+    /// Description from xkeyboard-config, like `German (no dead keys)`
+    pub full_name: String,
+    /// Position in the `sources` gsettings key
+    pub index: u32,
+    /// Type of the gsettings source, like `xkb`
+    pub source_type: String,
+}
+
+impl ActiveSource {
+    /// The `(ssus)` value of the `ActiveSource` D-Bus property.
+    pub fn dbus_value(&self) -> Variant {
+        (
+            self.short_name.as_str(),
+            self.full_name.as_str(),
+            self.index,
+            self.source_type.as_str(),
+        )
+            .to_variant()
+    }
+}
+
+/// A Unix socket in `$XDG_RUNTIME_DIR` streaming the active source as JSON
+/// lines. Clients get the current source when connecting, then every
+/// change.
+pub struct LayoutStream {
+    path: PathBuf,
+    accepting: Option<SourceId>,
+    clients: Rc<RefCell<Vec<UnixStream>>>,
+    current: Rc<RefCell<Option<String>>>,
+}
+
+impl LayoutStream {
+    pub fn bind() -> io::Result<LayoutStream> {
+        let dir = env::var_os("XDG_RUNTIME_DIR")
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "XDG_RUNTIME_DIR is not set"))?;
+        LayoutStream::bind_at(PathBuf::from(dir).join(SOCKET_NAME))
+    }
+
+    fn bind_at(path: PathBuf) -> io::Result<LayoutStream> {
+        // A previous instance that didn't exit cleanly leaves its socket
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        info!("Publishing the active input source on {}", path.display());
+
+        let clients: Rc<RefCell<Vec<UnixStream>>> = Rc::default();
+        let current: Rc<RefCell<Option<String>>> = Rc::default();
+        let (accepted, line) = (clients.clone(), current.clone());
+        let fd = listener.as_raw_fd();
+        let accepting = glib::unix_fd_add_local(fd, IOCondition::IN, move |_, _| {
+            loop {
+                match listener.accept() {
+                    Ok((mut client, _)) => {
+                        if let Some(line) = line.borrow().as_deref() {
+                            if client.write_all(line.as_bytes()).is_err() {
+                                continue;
+                            }
+                        }
+                        if client.set_nonblocking(true).is_ok() {
+                            accepted.borrow_mut().push(client);
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        warn!("Failed to accept a layout stream client: {e}");
+                        break;
+                    }
+                }
+            }
+            Continue(true)
+        });
+        Ok(LayoutStream {
+            path,
+            accepting: Some(accepting),
+            clients,
+            current,
+        })
+    }
+
+    /// Sends `source` to every client, dropping the ones that went away or
+    /// don't keep up.
+    pub fn publish(&self, source: &ActiveSource) {
+        let mut line = match serde_json::to_string(source) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("{e}");
+                return;
+            }
+        };
+        line.push('\n');
+        self.clients.borrow_mut().retain_mut(|client| {
+            let sent = client.write_all(line.as_bytes());
+            if let Err(e) = &sent {
+                debug!("Dropping layout stream client: {e}");
+            }
+            sent.is_ok()
+        });
+        *self.current.borrow_mut() = Some(line);
+    }
+}
+
+impl Drop for LayoutStream {
+    fn drop(&mut self) {
+        if let Some(accepting) = self.accepting.take() {
+            accepting.remove();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_bus::TestBus;
+    use glib::{MainContext, PRIORITY_DEFAULT};
+    use std::process;
+
+    #[test]
+    fn socket_is_removed_once_the_source_owning_the_stream_is() {
+        // Owns the default main context the listener is attached to
+        let _bus = TestBus::up();
+        let dir = env::temp_dir().join(format!("regolith-inputd-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(SOCKET_NAME);
+        let stream = LayoutStream::bind_at(path.clone()).unwrap();
+        assert!(path.exists());
+
+        // Like the manager, the stream lives in the closure of a source
+        let (_sender, receiver) = MainContext::channel::<()>(PRIORITY_DEFAULT);
+        let source = receiver.attach(None, move |_| {
+            stream.publish(&ActiveSource::default());
+            Continue(true)
+        });
+        source.remove();
+        assert!(!path.exists());
+        assert!(UnixStream::connect(&path).is_err());
+        fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -1,37 +1,116 @@
+mod batch;
+mod coalesce;
+mod command;
+mod dbus;
+mod error;
+mod fence;
 mod input_sources;
+mod keybindings;
 mod keyboard;
+mod layout;
+mod localed;
+mod logind;
 mod mouse;
+mod notify;
+mod options;
+mod policy;
+mod snippet;
+mod state;
+mod status;
+mod sway_config;
+mod sway_source;
+#[cfg(test)]
+mod test_bus;
+mod tick;
 mod touchpad;
 mod traits;
 mod utils;
 
+use batch::{CommandBatch, CommandResult};
+use coalesce::Coalescer;
+use command::{InputCommand, InputTarget};
+use dbus::{DBusCall, DBusService};
+use error::Result;
+use fence::TickFence;
+use gio::prelude::SettingsExtManual;
+use gio::traits::{ApplicationExt, SettingsExt};
+use gio::{BusType, Cancellable, Settings};
+use glib::{Continue, MainContext, Receiver, Sender, SourceId, ToVariant, PRIORITY_DEFAULT};
 use input_sources::InputSourcesHandler;
+use keybindings::SourceKeybindings;
 use keyboard::KeyboardHandler;
+use layout::LayoutStream;
 use log::info;
-use log::{debug, warn};
+use log::{debug, error, warn};
+use logind::SessionLockWatch;
 use mouse::MouseHandler;
+use notify::SourceNotifier;
+use options::DaemonOptions;
+use policy::Reconciliation;
 use serde::Deserialize;
-use std::error::Error;
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc, Mutex,
-};
-use std::thread;
+use snippet::ConfigSnippet;
+use state::{observed_property, DesiredState, PropertyValue};
+use status::DaemonStatus;
+use std::collections::BTreeMap;
 use std::time::Duration;
-use swayipc::{Connection as SwayConnection, Event, TickEvent};
+use sway_config::{ConfigProperties, PinnedProperties};
+use sway_source::SwayEventSource;
+use swayipc::{Connection as SwayConnection, Event, Fallible, Input, InputChange, TickEvent};
+use tick::{TickCommand, TickPayload};
 use touchpad::TouchpadHandler;
 use traits::InputHandler;
 
-static mut ALLOW_SWAYINPUT_APPLY: AtomicBool = AtomicBool::new(true);
-static mut ALLOW_GSETTINGS_APPLY: AtomicBool = AtomicBool::new(true);
+pub use batch::set_dry_run;
+pub use error::InputdError;
 
 // Type Aliases
-type SharedRef<T> = Arc<Mutex<T>>;
-type HandlerList = SharedRef<[Box<dyn InputHandler + Send>; 4]>;
+type HandlerList = Vec<Box<dyn InputHandler>>;
 
 // Structs
 pub struct SettingsManager {
     handlers: HandlerList,
+    sway_connection: SwayConnection,
+    sway_fence: TickFence,
+    gsettings_fence: Option<u64>,
+    gsettings_fence_count: u64,
+    reload_pending: bool,
+    status: DaemonStatus,
+    pinned: PinnedProperties,
+    snippet: Option<ConfigSnippet>,
+    dbus: Option<DBusService>,
+    layout_stream: Option<LayoutStream>,
+    keybindings: SourceKeybindings,
+    notifier: Option<SourceNotifier>,
+    lock_watch: Option<SessionLockWatch>,
+    /// Input source to restore when the session is unlocked
+    source_before_lock: Option<u32>,
+    options: DaemonOptions,
+    dependency_settings: Vec<Settings>,
+    gsettings_changes: Coalescer<(usize, String), ()>,
+    sway_inputs: Coalescer<String, Box<Input>>,
+    sender: Sender<Message>,
+    receiver: Option<Receiver<Message>>,
+}
+
+/// Everything that has to touch a handler is sent here and dispatched on the
+/// main context, which is the only owner of the handlers.
+pub(crate) enum Message {
+    GSettingsChanged(usize, String),
+    /// Queued behind the `changed` signals emitted by a back-sync
+    GSettingsFence(u64),
+    SwayInput(InputChange, Box<Input>),
+    SwayTick(String),
+    /// End of a coalescing window
+    FlushGSettingsChanges,
+    FlushSwayInputs,
+    DBusCall(DBusCall),
+    /// One of our input source keybindings was pressed
+    SwitchSource {
+        backward: bool,
+    },
+    KeybindingsChanged,
+    /// logind asked to lock or unlock the session
+    SessionLocked(bool),
 }
 
 #[derive(Debug, Deserialize, PartialEq, Eq)]
@@ -49,82 +128,804 @@ struct SwayReloadTick {
 // Method Implementations
 impl SettingsManager {
     pub fn new() -> SettingsManager {
-        utils::retry_action(SwayConnection::new, 5, Duration::from_millis(500));
-        let handlers: HandlerList = Arc::new(Mutex::new([
-            Box::new(MouseHandler::new()),
-            Box::new(KeyboardHandler::new()),
-            Box::new(TouchpadHandler::new()),
-            Box::new(InputSourcesHandler::new()),
-        ]));
-        SettingsManager { handlers }
+        let sway_connection =
+            utils::retry_action(SwayConnection::new, 5, Duration::from_millis(500));
+        let handlers: HandlerList = [
+            MouseHandler::new().map(|h| Box::new(h) as Box<dyn InputHandler>),
+            KeyboardHandler::new().map(|h| Box::new(h) as Box<dyn InputHandler>),
+            TouchpadHandler::new().map(|h| Box::new(h) as Box<dyn InputHandler>),
+            InputSourcesHandler::new().map(|h| Box::new(h) as Box<dyn InputHandler>),
+        ]
+        .into_iter()
+        .filter_map(|handler| match handler {
+            Ok(handler) => Some(handler),
+            Err(e) => {
+                warn!("{e}, disabling its handler");
+                None
+            }
+        })
+        .collect();
+        for handler in handlers.iter() {
+            let schema = handler.settings().schema_id();
+            for key in handler.keys().iter().filter(|key| !handler.has_key(key)) {
+                warn!(
+                    "{} has no key {key}, it won't be applied",
+                    schema.as_deref().unwrap_or("?")
+                );
+            }
+        }
+        let options = DaemonOptions::load();
+        let (sender, receiver) = MainContext::channel(PRIORITY_DEFAULT);
+        let mut manager = SettingsManager {
+            handlers,
+            sway_connection,
+            sway_fence: TickFence::new(),
+            gsettings_fence: None,
+            gsettings_fence_count: 0,
+            reload_pending: false,
+            status: DaemonStatus::new(),
+            pinned: PinnedProperties::new(),
+            snippet: options.config_snippet.clone().map(ConfigSnippet::new),
+            dbus: None,
+            layout_stream: None,
+            keybindings: SourceKeybindings::new(),
+            notifier: None,
+            lock_watch: None,
+            source_before_lock: None,
+            options,
+            dependency_settings: Vec::new(),
+            gsettings_changes: Coalescer::new(),
+            sway_inputs: Coalescer::new(),
+            sender,
+            receiver: Some(receiver),
+        };
+        manager.load_pinned();
+        manager
     }
 
-    pub fn start_monitoring(&mut self) -> Result<(), Box<dyn Error + '_>> {
-        let mut handlers_lock = self.handlers.lock()?;
-        for handle in handlers_lock.iter_mut() {
-            handle.apply_all_sync()?;
-            handle.monitor_gsettings_change();
+    /// Applies the current gsettings state and attaches the manager to the
+    /// default main context. Must be called from the thread owning it.
+    /// Removing the returned source drops the manager.
+    pub fn start_monitoring(mut self) -> Result<SourceId> {
+        // Subscribe before applying anything so the first fence is seen
+        let events = utils::retry_action(
+            || SwayEventSource::subscribe(&["input", "tick", "binding"]),
+            5,
+            Duration::from_millis(500),
+        );
+
+        if !batch::is_dry_run() {
+            self.handlers
+                .iter()
+                .for_each(|handler| handler.connect_system());
         }
+        self.reconcile()?;
 
-        let handlers_sref = self.handlers.clone();
-        thread::spawn(move || Self::monitor_swayinput_events(handlers_sref));
+        match gio::Application::default().and_then(|app| app.dbus_connection()) {
+            Some(connection) => match DBusService::register(connection, self.sender.clone()) {
+                Ok(service) => self.dbus = Some(service),
+                Err(e) => warn!("{e}, the D-Bus interface won't be available"),
+            },
+            None => warn!("Not on the session bus, the D-Bus interface won't be available"),
+        }
+        if let Err(e) = self.keybindings.register(&mut self.sway_connection) {
+            warn!("Failed to register input source keybindings: {e}");
+        }
+        self.keybindings.monitor(self.sender.clone());
+        match LayoutStream::bind() {
+            Ok(stream) => self.layout_stream = Some(stream),
+            Err(e) => warn!("{e}, the input source won't be streamed"),
+        }
+        match self.sway_connection.get_inputs() {
+            Ok(devices) => devices.iter().for_each(|input| self.track_layout(input)),
+            Err(e) => warn!("{e}"),
+        }
+        let lock_watch = gio::bus_get_sync(BusType::System, Cancellable::NONE)
+            .map_err(InputdError::DBus)
+            .and_then(|connection| SessionLockWatch::watch(connection, self.sender.clone()));
+        match lock_watch {
+            Ok(watch) => self.lock_watch = Some(watch),
+            Err(e) => warn!("{e}, the input source won't change while locked"),
+        }
+        // Created after the initial tracking, which isn't a change to notify
+        self.notifier = gio::Application::default()
+            .and_then(|app| app.dbus_connection())
+            .map(SourceNotifier::new);
+
+        for (index, handle) in self.handlers.iter().enumerate() {
+            handle.monitor_gsettings_change(index, self.sender.clone());
+            let dependencies = handle.monitor_dependencies(index, self.sender.clone());
+            self.dependency_settings.extend(dependencies);
+        }
+
+        let sender = self.sender.clone();
+        events.attach(
+            move |event| {
+                if let Some(message) = Self::event_message(event) {
+                    if let Err(e) = sender.send(message) {
+                        error!("{e}");
+                    }
+                }
+            },
+            || {
+                warn!("Sway closed the IPC connection, shutting down");
+                if let Some(app) = gio::Application::default() {
+                    app.quit();
+                }
+            },
+        );
+
+        let receiver = self.receiver.take().expect("Manager is started only once");
+        let source = receiver.attach(None, move |message| {
+            self.handle_message(message);
+            Continue(true)
+        });
+        Ok(source)
+    }
+
+    /// Applies every handler once, without monitoring anything.
+    pub fn apply_once(mut self) -> Result<()> {
+        let result = self.apply_all();
+        self.write_config_snippet();
+        Settings::sync();
+        result
+    }
+
+    /// Prints the desired state of each device next to the state sway
+    /// reports, marking the properties that differ.
+    pub fn print_status(&mut self) -> Result<()> {
+        let devices = self.sway_connection.get_inputs()?;
+        for device in devices.iter() {
+            println!("{} ({})", device.identifier, device.input_type);
+            for handler in self.handlers.iter() {
+                if handler.input_type() != device.input_type {
+                    continue;
+                }
+                for prop in handler.desired_state()? {
+                    let observed = observed_property(device, prop.property)
+                        .map_or_else(|| String::from("?"), |value| value.to_string());
+                    let pinned = self
+                        .pinned
+                        .pins(&device.input_type, prop.property, &devices);
+                    let mark = match observed_property(device, prop.property) {
+                        _ if pinned => "pinned by the sway config",
+                        Some(value) if !prop.value.matches(&value) => "differs",
+                        _ => "",
+                    };
+                    println!(
+                        "    {:<18} gsettings: {:<12} sway: {observed:<12} {mark}",
+                        prop.property,
+                        prop.value.to_string(),
+                    );
+                }
+            }
+        }
         Ok(())
     }
 
-    fn monitor_swayinput_events(mut handlers_sref: HandlerList) {
-        let event_stream = utils::retry_action(
-            utils::get_new_inputevent_stream,
-            5,
-            Duration::from_millis(500),
-        );
-        for event in event_stream {
-            match event {
-                Ok(Event::Input(event))
-                    if unsafe { ALLOW_SWAYINPUT_APPLY.load(Ordering::Relaxed) } =>
-                {
-                    utils::sync_input_gsettings(&mut handlers_sref, &event.input).unwrap();
-                }
-                Ok(Event::Tick(TickEvent {
-                    payload,
-                    first: false,
-                    ..
-                })) => {
-                    use SwayReloadStatus::{ReloadDone, ReloadPending};
-                    match serde_json::from_str::<SwayReloadTick>(&payload) {
-                        Ok(SwayReloadTick {
-                            status: ReloadPending,
-                        }) => unsafe {
-                            ALLOW_SWAYINPUT_APPLY.store(false, Ordering::Relaxed);
-                            info!(
-                                "Recieved tick, allow_sync = {}",
-                                ALLOW_SWAYINPUT_APPLY.load(Ordering::Relaxed)
-                            );
-                        },
-                        Ok(SwayReloadTick { status: ReloadDone }) => {
-                            thread::sleep(Duration::from_millis(100));
-                            unsafe {
-                                ALLOW_SWAYINPUT_APPLY.store(true, Ordering::Relaxed);
-                                info!(
-                                    "Recieved tick, allow_sync = {}",
-                                    ALLOW_SWAYINPUT_APPLY.load(Ordering::Relaxed)
-                                );
-                            }
-                            info!("Sway reload done - Reapplying configurations from gsettings");
-                            let mut handlers_lock = handlers_sref
-                                .lock()
-                                .expect("Acquired lock for handers_sref");
-                            for handle in handlers_lock.iter_mut() {
-                                handle
-                                    .apply_all_sync()
-                                    .expect("Failed to re-apply configs from gsettings");
-                            }
-                        }
-                        Err(e) => debug!("Invalid Payload Recieved: {e}"),
+    /// Prints the commands applying every handler would run.
+    pub fn print_diff(&mut self) -> Result<()> {
+        let mut pending = 0;
+        for index in 0..self.handlers.len() {
+            let desired = self.desired(index, None)?;
+            let batch = self.handlers[index].pending_commands(&desired)?;
+            for command in batch.commands() {
+                println!("{command}");
+                pending += 1;
+            }
+        }
+        if pending == 0 {
+            println!("sway already matches gsettings");
+        }
+        Ok(())
+    }
+
+    /// Imports the `input` settings of the sway config into gsettings,
+    /// printing every key that differs. Nothing is written unless `write`.
+    pub fn import_sway_config(&mut self, write: bool) -> Result<()> {
+        let snippet = self.snippet.as_ref().map(ConfigSnippet::path);
+        let settings = sway_config::input_settings(&mut self.sway_connection, snippet)?;
+        let devices = self.sway_connection.get_inputs()?;
+        let mut properties: BTreeMap<String, ConfigProperties> = BTreeMap::new();
+        for handler in self.handlers.iter() {
+            let input_type = handler.input_type();
+            if properties.contains_key(input_type) {
+                continue;
+            }
+            let mut merged = ConfigProperties::new();
+            for setting in settings
+                .iter()
+                .filter(|setting| setting.applies_to(input_type, &devices))
+            {
+                let previous = merged.insert(setting.property.clone(), setting.args.clone());
+                match previous {
+                    Some(previous) if previous != setting.args => println!(
+                        "conflict: {input_type} {} is {previous:?} before input {} sets {:?}",
+                        setting.property, setting.target, setting.args
+                    ),
+                    _ => {}
+                }
+            }
+            properties.insert(input_type.to_owned(), merged);
+        }
+
+        let mut changes = 0;
+        for handler in self.handlers.iter() {
+            let schema = handler.settings().schema_id();
+            let schema = schema.as_deref().unwrap_or("?");
+            for (key, value) in handler.imported_settings(&properties[handler.input_type()]) {
+                if !handler.has_key(key) {
+                    continue;
+                }
+                // Arguments sway accepts may still be outside of the schema
+                if !utils::in_range(handler.settings(), key, &value) {
+                    warn!("{value} is not a valid {schema} {key}, skipping it");
+                    continue;
+                }
+                let current = handler.settings().value(key);
+                if utils::same_value(&current, &value) {
+                    continue;
+                }
+                changes += 1;
+                if !handler.settings().is_writable(key) {
+                    println!("{schema} {key}: {current} -> {value} (locked, skipped)");
+                    continue;
+                }
+                println!("{schema} {key}: {current} -> {value}");
+                if write {
+                    handler.settings().set_value(key, &value)?;
+                }
+            }
+        }
+        if write {
+            Settings::sync();
+        } else if changes > 0 {
+            println!("Dry run, nothing was written. Run `import --write` to apply.");
+        }
+        if changes == 0 {
+            println!("gsettings already match the sway config");
+        }
+        Ok(())
+    }
+
+    /// Reconciles gsettings and sway in the direction picked by the startup
+    /// precedence.
+    fn reconcile(&mut self) -> Result<()> {
+        let precedence = self.options.startup_precedence;
+        // Our own snippet changes whenever gsettings are applied
+        let snippet = self.snippet.as_ref().map(ConfigSnippet::path);
+        let config_modified = sway_config::last_modified(&mut self.sway_connection, snippet);
+        let direction = precedence.resolve(self.status.gsettings_changed_at(), config_modified);
+        info!("Reconciling with precedence {precedence}: {direction:?}");
+        match direction {
+            Reconciliation::ApplyGSettings => {
+                let result = self.apply_all();
+                if !self.pinned.is_empty() {
+                    self.sync_from_sway(None)?;
+                }
+                self.write_config_snippet();
+                result
+            }
+            Reconciliation::ImportSway => {
+                self.sync_from_sway(None)?;
+                self.write_config_snippet();
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes the current gsettings state, minus what the sway config
+    /// pins, to the generated config snippet.
+    fn write_config_snippet(&mut self) {
+        let Some(snippet) = &self.snippet else {
+            return;
+        };
+        let devices = if self.pinned.is_empty() {
+            vec![]
+        } else {
+            match self.sway_connection.get_inputs() {
+                Ok(devices) => devices,
+                Err(e) => {
+                    warn!("Not writing the config snippet: {e}");
+                    return;
+                }
+            }
+        };
+        let mut commands = Vec::new();
+        for handler in self.handlers.iter() {
+            let input_type = handler.input_type();
+            let desired = match handler.desired_state() {
+                Ok(desired) => desired,
+                Err(e) => {
+                    warn!("{e}");
+                    continue;
+                }
+            };
+            for prop in desired {
+                if self.pinned.pins(input_type, prop.property, &devices) {
+                    continue;
+                }
+                let target = InputTarget::Type(input_type.to_owned());
+                match InputCommand::new(target, prop.property, &prop.value) {
+                    Ok(command) => commands.push(command),
+                    Err(e) => warn!("{e}"),
+                }
+            }
+        }
+        if let Err(e) = snippet.write(&commands) {
+            warn!("Failed to write {}: {e}", snippet.path().display());
+        }
+    }
+
+    /// Applies every handler, carrying on past failures, and returns the
+    /// last error.
+    fn apply_all(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for index in 0..self.handlers.len() {
+            if let Err(e) = self.apply(index, None) {
+                error!("Failed to apply configs from gsettings: {e}");
+                result = Err(e);
+            }
+        }
+        self.sway_fence.raise(&mut self.sway_connection);
+        result
+    }
+
+    /// Syncs `changed` devices, or all of them, back to gsettings.
+    fn sync_from_sway(&mut self, changed: Option<Vec<Input>>) -> Result<()> {
+        let devices = self.sway_connection.get_inputs()?;
+        let policy = self.options.back_sync_policy;
+        let resolved = match changed {
+            Some(changed) => policy.resolve(changed, &devices),
+            None => policy.resolve_all(devices),
+        };
+        for input in resolved {
+            if let Err(e) = utils::sync_input_gsettings(&mut self.handlers, &input) {
+                debug!("{e}");
+            }
+        }
+        self.raise_gsettings_fence();
+        Ok(())
+    }
+
+    fn load_pinned(&mut self) {
+        let snippet = self.snippet.as_ref().map(ConfigSnippet::path);
+        match PinnedProperties::load(&mut self.sway_connection, snippet) {
+            Ok(pinned) => self.pinned = pinned,
+            Err(e) => warn!("Failed to read the sway config: {e}"),
+        }
+    }
+
+    /// Applies `key` of the handler at `index`, or all of its keys, and
+    /// records the outcome of every command sent for them. IPC failures are
+    /// retried once on a new connection.
+    fn apply(&mut self, index: usize, key: Option<&str>) -> Result<()> {
+        let schema = self.handlers[index].settings().schema_id();
+        let schema = schema.as_deref().unwrap_or("?").to_owned();
+        let results = match self.apply_handler(index, key) {
+            Err(e) if e.is_transient() => {
+                warn!("{e}, reconnecting to sway");
+                self.handlers[index].reconnect()?;
+                self.apply_handler(index, key)
+            }
+            results => results,
+        };
+        let key = key.unwrap_or("*");
+        match results {
+            Ok(results) => {
+                self.status.clear_error(&schema, key);
+                self.status.record(&schema, &results);
+                Ok(())
+            }
+            Err(e) => {
+                if e.concerns_user() {
+                    self.status.record_error(&schema, key, &e);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn apply_handler(&mut self, index: usize, key: Option<&str>) -> Result<Vec<CommandResult>> {
+        let desired = self.desired(index, key)?;
+        self.handlers[index].reconcile(&desired)
+    }
+
+    /// Desired state of `key` of the handler at `index`, or of all of its
+    /// keys, without the properties the sway config pins.
+    fn desired(&mut self, index: usize, key: Option<&str>) -> Result<DesiredState> {
+        let handle = &self.handlers[index];
+        let mut desired = match key {
+            Some(key) => handle.desired_properties(key)?,
+            None => handle.desired_state()?,
+        };
+        if !self.pinned.is_empty() {
+            let input_type = handle.input_type().to_owned();
+            let devices = self.sway_connection.get_inputs()?;
+            desired.retain(|prop| {
+                let pinned = self.pinned.pins(&input_type, prop.property, &devices);
+                if pinned {
+                    info!("{} is pinned by the sway config, not applying it", prop.key);
+                }
+                !pinned
+            });
+        }
+        Ok(desired)
+    }
+
+    fn handle_message(&mut self, message: Message) {
+        match message {
+            Message::GSettingsChanged(index, key) => {
+                if self.gsettings_fence.is_some() {
+                    debug!("Ignoring gsettings change of {key} caused by sway");
+                    return;
+                }
+                if self.gsettings_changes.push((index, key), ()) {
+                    self.schedule(Message::FlushGSettingsChanges);
+                }
+            }
+            Message::GSettingsFence(id) => {
+                if self.gsettings_fence == Some(id) {
+                    self.gsettings_fence = None;
+                }
+            }
+            Message::SwayInput(change, input) => {
+                self.emit_input_signal(&change, &input);
+                if matches!(
+                    change,
+                    InputChange::Added | InputChange::XkbLayout | InputChange::XkbKeymap
+                ) {
+                    self.track_layout(&input);
+                }
+                if matches!(change, InputChange::Removed) {
+                    return;
+                }
+                if self.reload_pending || self.sway_fence.is_raised() {
+                    debug!("Ignoring input event for {} caused by us", input.identifier);
+                    return;
+                }
+                if self.sway_inputs.push(input.identifier.clone(), input) {
+                    self.schedule(Message::FlushSwayInputs);
+                }
+            }
+            Message::FlushGSettingsChanges => {
+                for ((index, key), ()) in self.gsettings_changes.take() {
+                    let schema = self.handlers[index].settings().schema_id();
+                    info!("{} -> Key: {key} changed", schema.as_deref().unwrap_or("?"));
+                    if let Err(e) = self.apply(index, Some(&key)) {
+                        error!("{e}");
                     }
                 }
+                self.sway_fence.raise(&mut self.sway_connection);
+                self.status.record_gsettings_change();
+                if !self.pinned.is_empty() {
+                    // Show the values of the config instead of the ignored ones
+                    if let Err(e) = self.sync_from_sway(None) {
+                        warn!("{e}");
+                    }
+                }
+                self.write_config_snippet();
+            }
+            Message::FlushSwayInputs => {
+                let changed = self
+                    .sway_inputs
+                    .take()
+                    .into_iter()
+                    .map(|(_, i)| *i)
+                    .collect();
+                if let Err(e) = self.sync_from_sway(Some(changed)) {
+                    warn!("{e}");
+                }
+                self.write_config_snippet();
+            }
+            Message::SwayTick(payload) => self.handle_tick(&payload),
+            Message::SwitchSource { backward } => {
+                if let Err(e) = self.switch_source(backward) {
+                    warn!("Failed to switch input source: {e}");
+                }
+            }
+            Message::SessionLocked(locked) => {
+                if let Err(e) = self.force_lock_source(locked) {
+                    warn!("Failed to switch the input source for the lock screen: {e}");
+                }
+            }
+            Message::KeybindingsChanged => {
+                if let Err(e) = self.keybindings.register(&mut self.sway_connection) {
+                    warn!("Failed to register input source keybindings: {e}");
+                }
+            }
+            Message::DBusCall(call) => {
+                let result = self.handle_dbus_call(&call.method, &call.parameters);
+                call.reply(result);
+            }
+        }
+    }
+
+    fn handle_dbus_call(
+        &mut self,
+        method: &str,
+        parameters: &glib::Variant,
+    ) -> Result<Option<glib::Variant>> {
+        match method {
+            "ReapplyAll" => {
+                self.apply_all()?;
+                self.write_config_snippet();
+                Ok(None)
+            }
+            "ListDevices" => {
+                let devices = self.sway_connection.get_inputs()?;
+                let entries: Vec<_> = devices.iter().map(dbus::device_entry).collect();
+                Ok(Some((entries,).to_variant()))
+            }
+            "SetDeviceProperty" => {
+                let (identifier, property, value) = parameters
+                    .get::<(String, String, String)>()
+                    .ok_or_else(|| InputdError::Validation(String::from("Invalid arguments")))?;
+                let property = command::INPUT_PROPERTIES
+                    .iter()
+                    .find(|known| **known == property)
+                    .copied()
+                    .ok_or_else(|| {
+                        InputdError::Validation(format!("Unknown property {property}"))
+                    })?;
+                let value = PropertyValue::parse(&value);
+                let target = InputTarget::Identifier(identifier);
+                // Per-device settings have no gsettings key to sync back to
+                self.run_input_command(target, property, value)?;
+                Ok(None)
+            }
+            _ => Err(InputdError::Unsupported(format!("Unknown method {method}"))),
+        }
+    }
+
+    /// Runs a command that isn't derived from gsettings, fenced so it isn't
+    /// synced back.
+    fn run_input_command(
+        &mut self,
+        target: InputTarget,
+        property: &'static str,
+        value: PropertyValue,
+    ) -> Result<()> {
+        let command = InputCommand::new(target, property, &value)?;
+        let mut batch = CommandBatch::new();
+        batch.push(property, value.to_string(), command.to_string());
+        info!("Executing command: {}", batch.payload());
+        let results = batch.run(&mut self.sway_connection)?;
+        self.sway_fence.raise(&mut self.sway_connection);
+        for result in results {
+            result.outcome?;
+        }
+        Ok(())
+    }
+
+    /// Cycles the input sources through the handler that has them.
+    fn switch_source(&mut self, backward: bool) -> Result<()> {
+        self.with_source_handler(|handler| handler.switch_source(backward))
+    }
+
+    /// Switches to the lock screen's input source when the session locks,
+    /// and back to the source used before when it unlocks. Neither switch
+    /// counts as using the source.
+    fn force_lock_source(&mut self, locked: bool) -> Result<()> {
+        if locked {
+            let Some(previous) = self.handlers.iter().find_map(|h| h.current_source()) else {
+                return Ok(());
+            };
+            let lock_source = self.options.locked_input_source;
+            info!("Session locked, switching from input source {previous} to {lock_source}");
+            self.source_before_lock.get_or_insert(previous);
+            self.with_source_handler(|handler| handler.switch_layout(lock_source))
+        } else {
+            let Some(previous) = self.source_before_lock.take() else {
+                return Ok(());
+            };
+            info!("Session unlocked, restoring input source {previous}");
+            self.with_source_handler(|handler| handler.switch_layout(previous))
+        }
+    }
+
+    /// Runs `switch` on the handler that has input sources.
+    fn with_source_handler<F>(&mut self, mut switch: F) -> Result<()>
+    where
+        F: FnMut(&mut dyn InputHandler) -> Result<Vec<CommandResult>>,
+    {
+        for index in 0..self.handlers.len() {
+            let results = match switch(self.handlers[index].as_mut()) {
+                Err(InputdError::Unsupported(_)) => continue,
+                results => results?,
+            };
+            let schema = self.handlers[index].settings().schema_id();
+            let schema = schema.as_deref().unwrap_or("?").to_owned();
+            self.status.record(&schema, &results);
+            // Neither the layout switch nor the writes to current have to be
+            // synced or applied again
+            self.sway_fence.raise(&mut self.sway_connection);
+            self.raise_gsettings_fence();
+            return Ok(());
+        }
+        Err(InputdError::Unsupported(String::from(
+            "No handler has input sources",
+        )))
+    }
+
+    fn run_tick_command(&mut self, command: TickCommand) -> Result<()> {
+        match command {
+            TickCommand::Reapply => {
+                self.apply_all()?;
+                self.write_config_snippet();
+            }
+            TickCommand::NextLayout => self.switch_source(false)?,
+            TickCommand::PreviousLayout => self.switch_source(true)?,
+            TickCommand::ToggleTouchpad => {
+                let settings = self
+                    .handlers
+                    .iter()
+                    .find(|handler| handler.input_type() == "touchpad")
+                    .map(|handler| handler.settings())
+                    .filter(|settings| utils::has_key(settings, "send-events"))
+                    .ok_or_else(|| {
+                        InputdError::Unsupported(String::from("No touchpad settings to toggle"))
+                    })?;
+                let events: String = settings.get("send-events");
+                let toggled = if events == "disabled" {
+                    "enabled"
+                } else {
+                    "disabled"
+                };
+                info!("Touchpad send-events {events} -> {toggled}");
+                settings.set_string("send-events", toggled)?;
+            }
+            TickCommand::Set { schema, key, value } => {
+                let settings = self
+                    .handlers
+                    .iter()
+                    .map(|handler| handler.settings())
+                    .find(|settings| settings.schema_id().as_deref() == Some(schema.as_str()))
+                    .ok_or_else(|| {
+                        InputdError::Unsupported(format!("Unmanaged schema {schema}"))
+                    })?;
+                if !utils::has_key(settings, &key) {
+                    return Err(InputdError::Settings(format!("{schema} has no key {key}")));
+                }
+                if !settings.is_writable(&key) {
+                    return Err(InputdError::Settings(format!("{schema} {key} is locked")));
+                }
+                let current = settings.value(&key);
+                let value = tick::json_to_variant(&value, current.type_())?;
+                if !utils::in_range(settings, &key, &value) {
+                    return Err(InputdError::Validation(format!(
+                        "{value} is out of range for {schema} {key}"
+                    )));
+                }
+                info!("{schema} {key}: {current} -> {value}");
+                settings.set_value(&key, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Publishes the input source of a keyboard when it changed, whether or
+    /// not the change was caused by us.
+    fn track_layout(&mut self, input: &Input) {
+        for handler in self.handlers.iter_mut() {
+            if handler.input_type() != input.input_type {
+                continue;
+            }
+            if let Some(source) = handler.track_layout(input) {
+                if let Some(dbus) = &self.dbus {
+                    dbus.set_active_source(&source);
+                }
+                if let Some(stream) = &self.layout_stream {
+                    stream.publish(&source);
+                }
+                if let Some(notifier) = &self.notifier {
+                    notifier.notify(&source);
+                }
+            }
+        }
+    }
+
+    /// Emits the D-Bus signals matching an input event.
+    fn emit_input_signal(&self, change: &InputChange, input: &Input) {
+        let Some(dbus) = &self.dbus else {
+            return;
+        };
+        let device = (input.identifier.as_str(), input.input_type.as_str());
+        match change {
+            InputChange::Added => dbus.emit("DeviceAdded", device.to_variant()),
+            InputChange::Removed => dbus.emit("DeviceRemoved", device.to_variant()),
+            InputChange::XkbLayout => {
+                let layout = input.xkb_active_layout_name.clone().unwrap_or_default();
+                let parameters = (input.identifier.as_str(), layout.as_str());
+                dbus.emit("LayoutChanged", parameters.to_variant());
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_tick(&mut self, payload: &str) {
+        if self.sway_fence.try_lower(payload) {
+            return;
+        }
+        use SwayReloadStatus::{ReloadDone, ReloadPending};
+        match serde_json::from_str::<SwayReloadTick>(payload) {
+            Ok(SwayReloadTick {
+                status: ReloadPending,
+            }) => {
+                self.reload_pending = true;
+                info!("Recieved tick, reload pending");
+            }
+            Ok(SwayReloadTick { status: ReloadDone }) => {
+                self.reload_pending = false;
+                info!("Sway reload done - Reconciling gsettings and sway");
+                self.load_pinned();
+                // Runtime bindings don't survive the reload
+                self.keybindings.forget();
+                if let Err(e) = self.keybindings.register(&mut self.sway_connection) {
+                    warn!("Failed to register input source keybindings: {e}");
+                }
+                if let Err(e) = self.reconcile() {
+                    error!("Failed to reconcile gsettings and sway: {e}");
+                }
+            }
+            Err(_) => match TickPayload::parse(payload) {
+                Ok(TickPayload::Command(command)) => {
+                    info!("Tick command: {command:?}");
+                    if let Err(e) = self.run_tick_command(command) {
+                        warn!("Tick command failed: {e}");
+                    }
+                }
+                Ok(TickPayload::Foreign) => debug!("Ignoring tick: {payload}"),
                 Err(e) => warn!("{e}"),
-                _ => continue,
+            },
+        }
+    }
+
+    /// Sends `message` to the manager once the coalescing window elapsed.
+    fn schedule(&self, message: Message) {
+        let sender = self.sender.clone();
+        glib::timeout_add_local_once(self.options.coalesce_window, move || {
+            if let Err(e) = sender.send(message) {
+                error!("{e}");
+            }
+        });
+    }
+
+    /// Queues a marker behind the `changed` signals emitted by the gsettings
+    /// writes done so far. Changes are ignored until the marker is dispatched.
+    fn raise_gsettings_fence(&mut self) {
+        self.gsettings_fence_count += 1;
+        self.gsettings_fence = Some(self.gsettings_fence_count);
+        if let Err(e) = self
+            .sender
+            .send(Message::GSettingsFence(self.gsettings_fence_count))
+        {
+            error!("{e}");
+        }
+    }
+
+    /// Turns a sway event into the message handling it, if it needs one.
+    fn event_message(event: Fallible<Event>) -> Option<Message> {
+        match event {
+            Ok(Event::Input(event)) => {
+                let event = *event;
+                Some(Message::SwayInput(event.change, Box::new(event.input)))
+            }
+            Ok(Event::Binding(event)) => match event.binding.command.as_str() {
+                keybindings::FORWARD_COMMAND => Some(Message::SwitchSource { backward: false }),
+                keybindings::BACKWARD_COMMAND => Some(Message::SwitchSource { backward: true }),
+                _ => None,
+            },
+            Ok(Event::Tick(TickEvent {
+                payload,
+                first: false,
+                ..
+            })) => Some(Message::SwayTick(payload)),
+            Err(e) => {
+                warn!("{e}");
+                None
             }
+            _ => None,
         }
     }
 }
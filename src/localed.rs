@@ -0,0 +1,141 @@
+use gio::traits::DBusProxyExt;
+use gio::{prelude::SettingsExtManual, traits::SettingsExt, Settings};
+use gio::{BusType, Cancellable, DBusCallFlags, DBusProxy, DBusProxyFlags};
+use glib::ToVariant;
+use log::{info, warn};
+
+use crate::input_sources::xkb_sources;
+use crate::utils;
+
+const LOCALED_NAME: &str = "org.freedesktop.locale1";
+const LOCALED_PATH: &str = "/org/freedesktop/locale1";
+
+/// The X11 keymap configured system-wide in systemd-localed, used by the
+/// greeter and, converted, by the TTY.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SystemKeymap {
+    pub layout: String,
+    pub model: String,
+    pub variant: String,
+    pub options: String,
+}
+
+impl SystemKeymap {
+    /// Reads the keymap from localed, if it runs and has a layout set.
+    pub fn read() -> Option<SystemKeymap> {
+        let proxy = match proxy() {
+            Ok(proxy) => proxy,
+            Err(e) => {
+                warn!("Can't reach systemd-localed: {e}");
+                return None;
+            }
+        };
+        let property = |name: &str| -> String {
+            proxy
+                .cached_property(name)
+                .and_then(|value| value.get::<String>())
+                .unwrap_or_default()
+        };
+        let keymap = SystemKeymap {
+            layout: property("X11Layout"),
+            model: property("X11Model"),
+            variant: property("X11Variant"),
+            options: property("X11Options"),
+        };
+        if keymap.layout.is_empty() {
+            return None;
+        }
+        Some(keymap)
+    }
+
+    /// The keymap as gsettings `sources`, one per layout.
+    pub fn sources(&self) -> Vec<(String, String)> {
+        xkb_sources(&self.layout, &self.variant)
+    }
+
+    pub fn options(&self) -> Vec<String> {
+        self.options
+            .split(',')
+            .filter(|option| !option.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    /// Writes the keymap to `sources` and `xkb-options` of the
+    /// input-sources `settings` unless the user ever set them.
+    pub fn seed(&self, settings: &Settings) {
+        let unset = |key: &str| utils::has_key(settings, key) && settings.user_value(key).is_none();
+        if unset("sources") && settings.get::<Vec<(String, String)>>("sources").is_empty() {
+            info!(
+                "Seeding input sources from systemd-localed: {}",
+                self.layout
+            );
+            if let Err(e) = settings.set("sources", &self.sources()) {
+                warn!("{e}");
+            }
+        }
+        if unset("xkb-options") && !self.options.is_empty() {
+            if let Err(e) = settings.set("xkb-options", &self.options()) {
+                warn!("{e}");
+            }
+        }
+    }
+
+    /// Makes the first of `sources` the system keymap, keeping the model
+    /// and options configured in localed. The keymap of the TTY is
+    /// converted from it.
+    pub fn push_first_source(sources: &[(String, String)]) {
+        let Some((source_type, id)) = sources.first() else {
+            return;
+        };
+        if source_type != "xkb" {
+            return;
+        }
+        let (layout, variant) = id.split_once('+').unwrap_or((id, ""));
+        let current = SystemKeymap::read().unwrap_or_default();
+        if current.layout == layout && current.variant == variant {
+            return;
+        }
+        let proxy = match proxy() {
+            Ok(proxy) => proxy,
+            Err(e) => {
+                warn!("Can't reach systemd-localed: {e}");
+                return;
+            }
+        };
+        info!("Setting the system keymap to {id}");
+        let parameters = (
+            layout,
+            current.model.as_str(),
+            variant,
+            current.options.as_str(),
+            true,
+            false,
+        )
+            .to_variant();
+        proxy.call(
+            "SetX11Keyboard",
+            Some(&parameters),
+            DBusCallFlags::NONE,
+            -1,
+            Cancellable::NONE,
+            |reply| {
+                if let Err(e) = reply {
+                    warn!("Failed to set the system keymap: {e}");
+                }
+            },
+        );
+    }
+}
+
+fn proxy() -> Result<DBusProxy, glib::Error> {
+    DBusProxy::for_bus_sync(
+        BusType::System,
+        DBusProxyFlags::NONE,
+        None,
+        LOCALED_NAME,
+        LOCALED_PATH,
+        LOCALED_NAME,
+        Cancellable::NONE,
+    )
+}
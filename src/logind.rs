@@ -0,0 +1,252 @@
+use gio::SignalSubscriptionId;
+use gio::{Cancellable, DBusCallFlags, DBusConnection, DBusSignalFlags};
+use glib::{Sender, ToVariant, Variant, VariantTy};
+use log::{error, info};
+use std::{env, process};
+
+use crate::error::{InputdError, Result};
+use crate::Message;
+
+const LOGIND_NAME: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const USER_INTERFACE: &str = "org.freedesktop.login1.User";
+const SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+
+/// Forwards the `Lock` and `Unlock` signals of our logind session, found on
+/// the system bus `connection`.
+pub struct SessionLockWatch {
+    connection: DBusConnection,
+    subscription: Option<SignalSubscriptionId>,
+}
+
+impl SessionLockWatch {
+    pub fn watch(connection: DBusConnection, sender: Sender<Message>) -> Result<SessionLockWatch> {
+        let session = session_path(&connection)?;
+        info!("Watching logind session {session} for locks");
+        let subscription = connection.signal_subscribe(
+            Some(LOGIND_NAME),
+            Some(SESSION_INTERFACE),
+            None,
+            Some(&session),
+            None,
+            DBusSignalFlags::NONE,
+            move |_, _, _, _, signal, _| {
+                let locked = match signal {
+                    "Lock" => true,
+                    "Unlock" => false,
+                    _ => return,
+                };
+                if let Err(e) = sender.send(Message::SessionLocked(locked)) {
+                    error!("{e}");
+                }
+            },
+        );
+        Ok(SessionLockWatch {
+            connection,
+            subscription: Some(subscription),
+        })
+    }
+}
+
+impl Drop for SessionLockWatch {
+    fn drop(&mut self) {
+        if let Some(subscription) = self.subscription.take() {
+            self.connection.signal_unsubscribe(subscription);
+        }
+    }
+}
+
+/// Object path of the session the daemon runs in. As a systemd user
+/// service the daemon is outside of any session, so without
+/// `XDG_SESSION_ID` it takes the graphical session of its user.
+fn session_path(connection: &DBusConnection) -> Result<String> {
+    if let Ok(id) = env::var("XDG_SESSION_ID") {
+        let reply = call(
+            connection,
+            LOGIND_PATH,
+            MANAGER_INTERFACE,
+            "GetSession",
+            (id,).to_variant(),
+            "(o)",
+        )?;
+        return Ok(object_path(&reply.child_value(0)));
+    }
+    let reply = call(
+        connection,
+        LOGIND_PATH,
+        MANAGER_INTERFACE,
+        "GetUserByPID",
+        (process::id(),).to_variant(),
+        "(o)",
+    )?;
+    let user = object_path(&reply.child_value(0));
+    let reply = call(
+        connection,
+        &user,
+        "org.freedesktop.DBus.Properties",
+        "Get",
+        (USER_INTERFACE, "Display").to_variant(),
+        "(v)",
+    )?;
+    // The display session is a `(so)` of its id and object path
+    let display = reply.child_value(0).as_variant().map(|d| d.child_value(1));
+    match display.map(|path| object_path(&path)) {
+        Some(path) if path != "/" => Ok(path),
+        _ => Err(InputdError::Unsupported(String::from(
+            "User has no graphical logind session",
+        ))),
+    }
+}
+
+fn call(
+    connection: &DBusConnection,
+    path: &str,
+    interface: &str,
+    method: &str,
+    parameters: Variant,
+    reply_type: &str,
+) -> Result<Variant> {
+    connection
+        .call_sync(
+            Some(LOGIND_NAME),
+            path,
+            interface,
+            method,
+            Some(&parameters),
+            VariantTy::new(reply_type).ok(),
+            DBusCallFlags::NONE,
+            -1,
+            Cancellable::NONE,
+        )
+        .map_err(InputdError::DBus)
+}
+
+fn object_path(value: &Variant) -> String {
+    value.str().unwrap_or_default().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_bus::TestBus;
+    use gio::DBusNodeInfo;
+    use glib::{Continue, MainContext, PRIORITY_DEFAULT};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    const LOGIND_XML: &str = r#"
+<node>
+  <interface name="org.freedesktop.login1.Manager">
+    <method name="GetSession">
+      <arg type="s" direction="in"/>
+      <arg type="o" direction="out"/>
+    </method>
+    <method name="GetUserByPID">
+      <arg type="u" direction="in"/>
+      <arg type="o" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.login1.User">
+    <property name="Display" type="(so)" access="read"/>
+  </interface>
+</node>"#;
+    const USER_PATH: &str = "/org/freedesktop/login1/user/self";
+    const SESSION_PATH: &str = "/org/freedesktop/login1/session/c1";
+
+    fn path_variant(path: &str) -> Variant {
+        Variant::parse(VariantTy::new("o").ok(), &format!("'{path}'")).unwrap()
+    }
+
+    /// A logind whose only user has the graphical session c1.
+    fn mock_logind(connection: &DBusConnection) {
+        let node = DBusNodeInfo::for_xml(LOGIND_XML).unwrap();
+        let manager = node.lookup_interface(MANAGER_INTERFACE).unwrap();
+        connection
+            .register_object(
+                LOGIND_PATH,
+                &manager,
+                |_, _, _, _, _, _, invocation| {
+                    let path = path_variant(SESSION_PATH);
+                    let path = match invocation.method_name().as_str() {
+                        "GetUserByPID" => path_variant(USER_PATH),
+                        _ => path,
+                    };
+                    invocation.return_value(Some(&Variant::tuple_from_iter([path])));
+                },
+                |_, _, _, _, _| ().to_variant(),
+                |_, _, _, _, _, _| false,
+            )
+            .unwrap();
+        let user = node.lookup_interface(USER_INTERFACE).unwrap();
+        connection
+            .register_object(
+                USER_PATH,
+                &user,
+                |_, _, _, _, _, _, _| {},
+                |_, _, _, _, _| {
+                    let path = path_variant(SESSION_PATH);
+                    Variant::tuple_from_iter(["c1".to_variant(), path])
+                },
+                |_, _, _, _, _, _| false,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn lock_and_unlock_of_the_session_are_forwarded() {
+        let bus = TestBus::up();
+        let logind = bus.spawn_service(LOGIND_NAME, mock_logind);
+
+        let (sender, receiver) = MainContext::channel(PRIORITY_DEFAULT);
+        let locks: Rc<RefCell<Vec<bool>>> = Rc::default();
+        let received = locks.clone();
+        let receiving = receiver.attach(None, move |message| {
+            if let Message::SessionLocked(locked) = message {
+                received.borrow_mut().push(locked);
+            }
+            Continue(true)
+        });
+        let client = bus.connect();
+        let _watch = SessionLockWatch::watch(client.clone(), sender).unwrap();
+        // The bus has seen the subscription once it answers a later call
+        client
+            .call_sync(
+                Some("org.freedesktop.DBus"),
+                "/org/freedesktop/DBus",
+                "org.freedesktop.DBus",
+                "GetId",
+                None,
+                None,
+                DBusCallFlags::NONE,
+                -1,
+                Cancellable::NONE,
+            )
+            .unwrap();
+
+        for signal in ["Lock", "Unlock"] {
+            logind
+                .emit_signal(None, SESSION_PATH, SESSION_INTERFACE, signal, None)
+                .unwrap();
+        }
+        // Other sessions locking don't concern us
+        logind
+            .emit_signal(
+                None,
+                "/org/freedesktop/login1/session/c2",
+                SESSION_INTERFACE,
+                "Lock",
+                None,
+            )
+            .unwrap();
+        logind.flush_sync(Cancellable::NONE).unwrap();
+        bus.run_until(|| locks.borrow().len() >= 2);
+        // Let a stray signal of the other session arrive, if any
+        let deadline = Instant::now() + Duration::from_millis(200);
+        bus.run_until(|| Instant::now() > deadline);
+        // Sources left on the default context would run on other tests' threads
+        receiving.remove();
+        assert_eq!(*locks.borrow(), [true, false]);
+    }
+}
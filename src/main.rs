@@ -1,15 +1,74 @@
 use gio::{prelude::ApplicationExtManual, traits::ApplicationExt, Application, ApplicationFlags};
-use log::error;
-use regolith_inputd::SettingsManager;
+use glib::clone;
+use log::{error, info};
+use regolith_inputd::{set_dry_run, SettingsManager};
+use std::{env, process};
+
+const USAGE: &str =
+    "Usage: regolith-inputd [--dry-run] [apply --once | status | diff | import [--write]]";
+
+/// Same as `--dry-run` when set to anything but 0
+const DRY_RUN_VAR: &str = "REGOLITH_INPUTD_DRY_RUN";
 
 fn main() {
     pretty_env_logger::init();
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let dry_run_flag = args.iter().any(|arg| arg == "--dry-run");
+    args.retain(|arg| arg != "--dry-run");
+    let dry_run_var = env::var(DRY_RUN_VAR).map_or(false, |value| value != "0");
+    if dry_run_flag || dry_run_var {
+        info!("Dry run, sway commands are logged instead of sent");
+        set_dry_run(true);
+    }
+    let flags: Vec<&str> = args.iter().skip(1).map(String::as_str).collect();
+    let result = match (args.first().map(String::as_str), flags.as_slice()) {
+        (None, []) => {
+            run_daemon();
+            Ok(())
+        }
+        (Some("apply"), ["--once"]) => SettingsManager::new().apply_once(),
+        (Some("status"), []) => SettingsManager::new().print_status(),
+        (Some("diff"), []) => SettingsManager::new().print_diff(),
+        (Some("import"), []) => SettingsManager::new().import_sway_config(false),
+        (Some("import"), ["--write"]) => SettingsManager::new().import_sway_config(true),
+        _ => {
+            eprintln!("{USAGE}");
+            process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
+
+fn run_daemon() {
     let app = Application::new(Some("org.regolith.inputd"), ApplicationFlags::IS_SERVICE);
-    let mut manager = SettingsManager::new();
-    if let Err(e) = manager.start_monitoring() {
+    // Registered early so the D-Bus interface can be exported on its connection
+    if let Err(e) = app.register(gio::Cancellable::NONE) {
         error!("{e}");
         panic!();
     }
+    let manager = SettingsManager::new();
+    let monitoring = match manager.start_monitoring() {
+        Ok(source) => source,
+        Err(e) => {
+            error!("{e}");
+            panic!();
+        }
+    };
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        glib::unix_signal_add_local(
+            signal,
+            clone!(@weak app => @default-return glib::Continue(false), move || {
+                info!("Received signal {signal}, shutting down");
+                app.quit();
+                glib::Continue(false)
+            }),
+        );
+    }
     app.hold();
     app.run();
+    // Drops the manager, which removes the layout stream socket
+    monitoring.remove();
 }
@@ -1,7 +1,11 @@
-use crate::traits::{InputHandler, PointerMethods, PrimitiveToSwayType, SwayTypeToPrimitive};
-use gio::{prelude::SettingsExtManual, traits::SettingsExt, Settings};
+use crate::error::Result;
+use crate::state::{DesiredProperty, DesiredState, PropertyValue};
+use crate::sway_config::{self, ConfigProperties};
+use crate::traits::{InputHandler, PointerMethods, SwayTypeToPrimitive};
+use crate::utils::{self, set_if_changed};
+use gio::{prelude::SettingsExtManual, Settings};
+use glib::{ToVariant, Variant};
 use log::info;
-use std::error::Error;
 use swayipc::{Connection as SwayConnection, Input};
 pub struct MouseHandler {
     settings: Settings,
@@ -9,44 +13,32 @@ pub struct MouseHandler {
 }
 
 impl MouseHandler {
-    pub fn new() -> MouseHandler {
-        let settings = Settings::new("org.gnome.desktop.peripherals.mouse");
-        let sway_connection = SwayConnection::new().unwrap();
-        MouseHandler {
+    pub fn new() -> Result<MouseHandler> {
+        let settings = utils::installed_settings("org.gnome.desktop.peripherals.mouse")?;
+        let sway_connection = SwayConnection::new()?;
+        Ok(MouseHandler {
             settings,
             sway_connection,
-        }
+        })
     }
 }
 
 impl PointerMethods for MouseHandler {
-    fn pointer_type(&self) -> &str {
-        "pointer"
-    }
-    fn apply_left_handed(&mut self) -> Result<(), Box<dyn Error>> {
-        let new_val: &str = self
-            .settings()
-            .get::<bool>("left-handed")
-            .to_sway_type()
-            .to_primitive();
-        let pointer_type = self.pointer_type();
-        let cmd = format!("input type:{pointer_type} left_handed {new_val}");
-        info!("Executing command: {cmd}");
-        self.sway_connection().run_command(cmd)?;
-        Ok(())
+    fn left_handed(&self) -> DesiredProperty {
+        let new_val: bool = self.settings().get("left-handed");
+        DesiredProperty::new("left-handed", "left_handed", PropertyValue::Toggle(new_val))
     }
 }
 
 impl InputHandler for MouseHandler {
-    fn apply_changes(&mut self, key: &str) -> Result<(), Box<dyn Error>> {
-        info!("org.gnome.desktop.peripherals.mouse -> Key: {key} chaged");
-        match key {
-            "speed" => self.apply_speed()?,
-            "natural-scroll" => self.apply_natural_scroll()?,
-            "left-handed" => self.apply_left_handed()?,
-            _ => (),
-        };
-        Ok(())
+    fn input_type(&self) -> &str {
+        "pointer"
+    }
+    fn keys(&self) -> &[&'static str] {
+        &["speed", "left-handed", "natural-scroll"]
+    }
+    fn desired_properties(&self, key: &str) -> Result<DesiredState> {
+        Ok(self.pointer_properties(key).into_iter().collect())
     }
     fn settings(&self) -> &Settings {
         &self.settings
@@ -54,13 +46,7 @@ impl InputHandler for MouseHandler {
     fn sway_connection(&mut self) -> &mut swayipc::Connection {
         &mut self.sway_connection
     }
-    fn apply_all(&mut self) -> Result<(), Box<dyn Error>> {
-        self.apply_speed()?;
-        self.apply_left_handed()?;
-        self.apply_natural_scroll()?;
-        Ok(())
-    }
-    fn sync_gsettings(&mut self, input: &Input) -> Result<(), Box<dyn Error>> {
+    fn sync_gsettings(&mut self, input: &Input) -> Result<()> {
         info!("Syncronizing mouse input state of sway with gsettings...");
         self.sync_pointer_gsettings(input)?;
         if input.libinput.is_none() {
@@ -68,11 +54,17 @@ impl InputHandler for MouseHandler {
         }
         let libinput = input.libinput.as_ref().unwrap();
         if let Some(left_handed) = libinput.left_handed.as_ref() {
-            self.settings()
-                .set_boolean("left-handed", left_handed.to_primitive())?;
+            let left_handed: bool = left_handed.to_primitive();
+            set_if_changed(self.settings(), "left-handed", left_handed)?;
         }
         Ok(())
     }
+    fn imported_settings(&self, properties: &ConfigProperties) -> Vec<(&'static str, Variant)> {
+        let mut imported = self.imported_pointer_settings(properties);
+        let left_handed = properties.get("left_handed");
+        if let Some(left_handed) = left_handed.and_then(|arg| sway_config::parse_toggle(arg)) {
+            imported.push(("left-handed", left_handed.to_variant()));
+        }
+        imported
+    }
 }
-
-unsafe impl Send for MouseHandler {}
@@ -0,0 +1,201 @@
+use gio::{prelude::SettingsExtManual, Cancellable, DBusCallFlags, DBusConnection, Settings};
+use glib::{ToVariant, Variant, VariantTy};
+use log::{debug, warn};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::layout::ActiveSource;
+use crate::status::STATUS_SCHEMA;
+use crate::utils;
+
+const NOTIFICATIONS_NAME: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const ENABLED_KEY: &str = "notify-source-change";
+/// Changes closer together than this are merged into one notification
+const MIN_INTERVAL: Duration = Duration::from_millis(500);
+const EXPIRE_TIMEOUT_MS: i32 = 1500;
+
+/// Shows the active input source in a desktop notification when it
+/// changes, if enabled in the daemon's schema. Each notification replaces
+/// the previous one.
+pub struct SourceNotifier {
+    state: Rc<NotifierState>,
+}
+
+struct NotifierState {
+    connection: DBusConnection,
+    settings: Option<Settings>,
+    replaces_id: Cell<u32>,
+    last_sent: Cell<Option<Instant>>,
+    pending: RefCell<Option<ActiveSource>>,
+}
+
+impl SourceNotifier {
+    pub fn new(connection: DBusConnection) -> SourceNotifier {
+        SourceNotifier {
+            state: Rc::new(NotifierState {
+                connection,
+                settings: utils::settings_if_installed(STATUS_SCHEMA),
+                replaces_id: Cell::new(0),
+                last_sent: Cell::new(None),
+                pending: RefCell::new(None),
+            }),
+        }
+    }
+
+    pub fn notify(&self, source: &ActiveSource) {
+        let enabled = self.state.settings.as_ref().map_or(false, |settings| {
+            utils::has_key(settings, ENABLED_KEY) && settings.get::<bool>(ENABLED_KEY)
+        });
+        if enabled {
+            self.show(source);
+        }
+    }
+
+    /// Sends `source` now, or merges it into the next notification while
+    /// the last one is too recent.
+    fn show(&self, source: &ActiveSource) {
+        let waiting = self.state.pending.replace(Some(source.clone())).is_some();
+        if waiting {
+            return;
+        }
+        let elapsed = self.state.last_sent.get().map(|sent| sent.elapsed());
+        match elapsed {
+            Some(elapsed) if elapsed < MIN_INTERVAL => {
+                let state = self.state.clone();
+                glib::timeout_add_local_once(MIN_INTERVAL - elapsed, move || state.send_pending());
+            }
+            _ => self.state.send_pending(),
+        }
+    }
+}
+
+impl NotifierState {
+    fn send_pending(self: &Rc<Self>) {
+        let Some(source) = self.pending.take() else {
+            return;
+        };
+        self.last_sent.set(Some(Instant::now()));
+        let hints: HashMap<String, Variant> = HashMap::from([
+            (String::from("transient"), true.to_variant()),
+            (String::from("category"), "device".to_variant()),
+        ]);
+        let parameters = (
+            "regolith-inputd",
+            self.replaces_id.get(),
+            "input-keyboard",
+            source.full_name.as_str(),
+            source.short_name.as_str(),
+            Vec::<String>::new(),
+            hints,
+            EXPIRE_TIMEOUT_MS,
+        )
+            .to_variant();
+        let state = self.clone();
+        self.connection.call(
+            Some(NOTIFICATIONS_NAME),
+            NOTIFICATIONS_PATH,
+            NOTIFICATIONS_NAME,
+            "Notify",
+            Some(&parameters),
+            VariantTy::new("(u)").ok(),
+            DBusCallFlags::NONE,
+            -1,
+            Cancellable::NONE,
+            move |reply| match reply.map(|reply| reply.get::<(u32,)>()) {
+                Ok(Some((id,))) => state.replaces_id.set(id),
+                Ok(None) => debug!("Unexpected reply from the notification server"),
+                Err(e) => warn!("Failed to show the input source notification: {e}"),
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_bus::TestBus;
+    use gio::DBusNodeInfo;
+    use std::sync::{Arc, Mutex};
+
+    const SERVER_XML: &str = r#"
+<node>
+  <interface name="org.freedesktop.Notifications">
+    <method name="Notify">
+      <arg type="s" direction="in"/>
+      <arg type="u" direction="in"/>
+      <arg type="s" direction="in"/>
+      <arg type="s" direction="in"/>
+      <arg type="s" direction="in"/>
+      <arg type="as" direction="in"/>
+      <arg type="a{sv}" direction="in"/>
+      <arg type="i" direction="in"/>
+      <arg type="u" direction="out"/>
+    </method>
+  </interface>
+</node>"#;
+    const NOTIFICATION_ID: u32 = 7;
+
+    /// A notification server recording the `replaces_id` and summary of
+    /// every notification.
+    fn mock_server(connection: &DBusConnection) -> Arc<Mutex<Vec<(u32, String)>>> {
+        let node = DBusNodeInfo::for_xml(SERVER_XML).unwrap();
+        let interface = node.lookup_interface(NOTIFICATIONS_NAME).unwrap();
+        let shown: Arc<Mutex<Vec<(u32, String)>>> = Arc::default();
+        let recorded = shown.clone();
+        connection
+            .register_object(
+                NOTIFICATIONS_PATH,
+                &interface,
+                move |_, _, _, _, _, parameters, invocation| {
+                    let replaces_id = parameters.child_value(1).get::<u32>().unwrap();
+                    let summary = parameters.child_value(3).get::<String>().unwrap();
+                    recorded.lock().unwrap().push((replaces_id, summary));
+                    invocation.return_value(Some(&(NOTIFICATION_ID,).to_variant()));
+                },
+                |_, _, _, _, _| ().to_variant(),
+                |_, _, _, _, _, _| false,
+            )
+            .unwrap();
+        shown
+    }
+
+    fn source(short_name: &str) -> ActiveSource {
+        ActiveSource {
+            short_name: short_name.to_owned(),
+            full_name: short_name.to_uppercase(),
+            index: 0,
+            source_type: String::from("xkb"),
+        }
+    }
+
+    #[test]
+    fn bursts_are_merged_and_replace_the_last_notification() {
+        let bus = TestBus::up();
+        let server = bus.connect_as(NOTIFICATIONS_NAME);
+        let shown = mock_server(&server);
+        let notifier = SourceNotifier::new(bus.connect());
+
+        for short_name in ["us", "de", "fr"] {
+            notifier.show(&source(short_name));
+        }
+        bus.run_until(|| shown.lock().unwrap().len() == 2);
+        assert_eq!(
+            *shown.lock().unwrap(),
+            [
+                (0, String::from("US")),
+                (NOTIFICATION_ID, String::from("FR")),
+            ]
+        );
+
+        notifier.show(&source("us"));
+        bus.run_until(|| shown.lock().unwrap().len() == 3);
+        assert_eq!(
+            shown.lock().unwrap()[2],
+            (NOTIFICATION_ID, String::from("US"))
+        );
+        assert_eq!(notifier.state.replaces_id.get(), NOTIFICATION_ID);
+    }
+}
@@ -0,0 +1,68 @@
+use gio::prelude::SettingsExtManual;
+use log::{info, warn};
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::policy::{BackSyncPolicy, StartupPrecedence};
+
+use crate::status::STATUS_SCHEMA;
+use crate::utils;
+
+/// Daemon options read from the org.regolith.inputd schema when starting,
+/// falling back to the defaults if it isn't installed.
+#[derive(Debug, Clone)]
+pub struct DaemonOptions {
+    pub coalesce_window: Duration,
+    pub back_sync_policy: BackSyncPolicy,
+    pub startup_precedence: StartupPrecedence,
+    pub config_snippet: Option<PathBuf>,
+    pub locked_input_source: u32,
+}
+
+impl DaemonOptions {
+    pub fn load() -> DaemonOptions {
+        let mut options = DaemonOptions::default();
+        if let Some(settings) = utils::settings_if_installed(STATUS_SCHEMA) {
+            let window: u32 = settings.get("coalesce-window");
+            options.coalesce_window = Duration::from_millis(window.into());
+            let policy: String = settings.get("back-sync-policy");
+            match BackSyncPolicy::from_nick(&policy) {
+                Some(policy) => options.back_sync_policy = policy,
+                None => warn!("Unknown back-sync policy {policy}"),
+            }
+            let precedence: String = settings.get("startup-precedence");
+            match StartupPrecedence::from_nick(&precedence) {
+                Some(precedence) => options.startup_precedence = precedence,
+                None => warn!("Unknown startup precedence {precedence}"),
+            }
+            let snippet: String = settings.get("config-snippet");
+            options.config_snippet = expand_home(&snippet);
+            options.locked_input_source = settings.get("locked-input-source");
+        }
+        info!("Back-sync policy: {}", options.back_sync_policy);
+        info!("Startup precedence: {}", options.startup_precedence);
+        options
+    }
+}
+
+impl Default for DaemonOptions {
+    fn default() -> Self {
+        DaemonOptions {
+            coalesce_window: Duration::from_millis(50),
+            back_sync_policy: BackSyncPolicy::Unanimous,
+            startup_precedence: StartupPrecedence::GSettings,
+            config_snippet: None,
+            locked_input_source: 0,
+        }
+    }
+}
+
+/// Resolves a leading `~/`, treating an empty path as unset.
+fn expand_home(path: &str) -> Option<PathBuf> {
+    match path.strip_prefix("~/") {
+        _ if path.is_empty() => None,
+        Some(relative) => env::var_os("HOME").map(|home| PathBuf::from(home).join(relative)),
+        None => Some(PathBuf::from(path)),
+    }
+}
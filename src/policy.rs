@@ -0,0 +1,379 @@
+use log::info;
+use std::fmt::{self, Display};
+use std::time::SystemTime;
+use swayipc::{Input, Libinput};
+
+use crate::state::observed_property;
+
+/// Properties that are synced back from sway into gsettings.
+const SYNCED_PROPERTIES: &[&str] = &[
+    "pointer_accel",
+    "natural_scroll",
+    "left_handed",
+    "events",
+    "tap",
+    "drag",
+    "drag_lock",
+];
+
+/// How a back-sync resolves devices of one type that disagree on a value,
+/// since they all share one gsettings key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackSyncPolicy {
+    /// Only the device that changed last is synced.
+    MostRecent,
+    /// A value is synced only while every device of the type agrees on it.
+    Unanimous,
+}
+
+impl BackSyncPolicy {
+    pub fn from_nick(nick: &str) -> Option<BackSyncPolicy> {
+        match nick {
+            "most-recent" => Some(BackSyncPolicy::MostRecent),
+            "unanimous" => Some(BackSyncPolicy::Unanimous),
+            _ => None,
+        }
+    }
+
+    pub fn nick(&self) -> &'static str {
+        match self {
+            BackSyncPolicy::MostRecent => "most-recent",
+            BackSyncPolicy::Unanimous => "unanimous",
+        }
+    }
+
+    /// Returns the inputs to sync back out of the `changed` ones, in the
+    /// order they changed, given the current state of every device.
+    pub fn resolve(&self, changed: Vec<Input>, devices: &[Input]) -> Vec<Input> {
+        let disputes = self.disputes(&changed, devices);
+        without_disputed(changed, disputes)
+    }
+
+    /// Same as `resolve` when every device changed, like after a reload.
+    pub fn resolve_all(&self, devices: Vec<Input>) -> Vec<Input> {
+        let disputes = self.disputes(&devices, &devices);
+        without_disputed(devices, disputes)
+    }
+
+    /// Indices of the `changed` inputs to sync back, each with the
+    /// properties other devices disagree on.
+    fn disputes(&self, changed: &[Input], devices: &[Input]) -> Vec<(usize, Vec<&'static str>)> {
+        // One device per type: the others would be overwritten anyway
+        let mut latest: Vec<usize> = Vec::new();
+        for (index, input) in changed.iter().enumerate().rev() {
+            if latest
+                .iter()
+                .all(|&other| changed[other].input_type != input.input_type)
+            {
+                latest.push(index);
+            }
+        }
+        latest.reverse();
+
+        latest
+            .into_iter()
+            .map(|index| match self {
+                BackSyncPolicy::MostRecent => (index, vec![]),
+                BackSyncPolicy::Unanimous => (index, self.disagreements(&changed[index], devices)),
+            })
+            .collect()
+    }
+
+    fn disagreements(&self, input: &Input, devices: &[Input]) -> Vec<&'static str> {
+        let others: Vec<&Input> = devices
+            .iter()
+            .filter(|d| d.input_type == input.input_type && d.identifier != input.identifier)
+            .collect();
+        let mut disputed = Vec::new();
+        for &property in SYNCED_PROPERTIES {
+            let Some(value) = observed_property(input, property) else {
+                continue;
+            };
+            let disagrees = others.iter().any(|other| {
+                observed_property(other, property).map_or(false, |other| !value.matches(&other))
+            });
+            if disagrees {
+                info!(
+                    "{}: not syncing {property} back, other {} devices disagree (policy: {self})",
+                    input.identifier, input.input_type
+                );
+                disputed.push(property);
+            }
+        }
+        disputed
+    }
+}
+
+/// Takes the inputs picked by `disputes` out of `inputs`, with their
+/// disputed properties cleared.
+fn without_disputed(inputs: Vec<Input>, disputes: Vec<(usize, Vec<&str>)>) -> Vec<Input> {
+    let mut inputs: Vec<Option<Input>> = inputs.into_iter().map(Some).collect();
+    disputes
+        .into_iter()
+        .filter_map(|(index, properties)| {
+            let mut input = inputs[index].take()?;
+            if let Some(libinput) = input.libinput.as_mut() {
+                for property in properties {
+                    clear_property(libinput, property);
+                }
+            }
+            Some(input)
+        })
+        .collect()
+}
+
+impl Display for BackSyncPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.nick())
+    }
+}
+
+/// Which side wins when gsettings and sway are reconciled at startup and
+/// after a sway reload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupPrecedence {
+    GSettings,
+    Sway,
+    /// Whichever of gsettings and the sway config changed last
+    Newest,
+}
+
+/// Direction of the reconciliation picked by a `StartupPrecedence`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reconciliation {
+    ApplyGSettings,
+    ImportSway,
+}
+
+impl StartupPrecedence {
+    pub fn from_nick(nick: &str) -> Option<StartupPrecedence> {
+        match nick {
+            "gsettings" => Some(StartupPrecedence::GSettings),
+            "sway" => Some(StartupPrecedence::Sway),
+            "newest" => Some(StartupPrecedence::Newest),
+            _ => None,
+        }
+    }
+
+    pub fn nick(&self) -> &'static str {
+        match self {
+            StartupPrecedence::GSettings => "gsettings",
+            StartupPrecedence::Sway => "sway",
+            StartupPrecedence::Newest => "newest",
+        }
+    }
+
+    /// Picks the direction given when gsettings were last changed through
+    /// the daemon and when the sway config was last modified. Unknown times
+    /// fall back to applying gsettings.
+    pub fn resolve(
+        &self,
+        gsettings_changed: Option<SystemTime>,
+        sway_config_changed: Option<SystemTime>,
+    ) -> Reconciliation {
+        match (self, gsettings_changed, sway_config_changed) {
+            (StartupPrecedence::GSettings, _, _) => Reconciliation::ApplyGSettings,
+            (StartupPrecedence::Sway, _, _) => Reconciliation::ImportSway,
+            (StartupPrecedence::Newest, Some(gsettings), Some(sway)) if sway > gsettings => {
+                Reconciliation::ImportSway
+            }
+            (StartupPrecedence::Newest, _, _) => Reconciliation::ApplyGSettings,
+        }
+    }
+}
+
+impl Display for StartupPrecedence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.nick())
+    }
+}
+
+fn clear_property(libinput: &mut Libinput, property: &str) {
+    match property {
+        "pointer_accel" => libinput.accel_speed = None,
+        "natural_scroll" => libinput.natural_scroll = None,
+        "left_handed" => libinput.left_handed = None,
+        "events" => libinput.send_events = None,
+        "tap" => libinput.tap = None,
+        "drag" => libinput.tap_drag = None,
+        "drag_lock" => libinput.tap_drag_lock = None,
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::PropertyValue;
+    use std::time::Duration;
+
+    /// A pointer as reported by `get_inputs`.
+    fn pointer(identifier: &str, accel_speed: f64, natural_scroll: &str) -> Input {
+        serde_json::from_value(serde_json::json!({
+            "identifier": identifier,
+            "name": identifier,
+            "vendor": 1,
+            "product": 2,
+            "type": "pointer",
+            "libinput": {
+                "send_events": "enabled",
+                "accel_speed": accel_speed,
+                "natural_scroll": natural_scroll,
+                "left_handed": "disabled",
+            },
+        }))
+        .unwrap()
+    }
+
+    /// Two mice that disagree on the acceleration but not on the rest.
+    fn disagreeing_pointers() -> Vec<Input> {
+        vec![
+            pointer("1:2:Mouse", 0.5, "enabled"),
+            pointer("3:4:Trackball", -0.5, "enabled"),
+        ]
+    }
+
+    #[test]
+    fn most_recent_syncs_the_last_changed_device() {
+        let devices = disagreeing_pointers();
+        let changed = disagreeing_pointers();
+        let resolved = BackSyncPolicy::MostRecent.resolve(changed, &devices);
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].identifier, "3:4:Trackball");
+        assert_eq!(
+            observed_property(&resolved[0], "pointer_accel"),
+            Some(PropertyValue::Number(-0.5))
+        );
+        assert_eq!(
+            observed_property(&resolved[0], "natural_scroll"),
+            Some(PropertyValue::Toggle(true))
+        );
+    }
+
+    #[test]
+    fn unanimous_drops_only_disputed_properties() {
+        let devices = disagreeing_pointers();
+        let changed = vec![pointer("1:2:Mouse", 0.5, "enabled")];
+        let resolved = BackSyncPolicy::Unanimous.resolve(changed, &devices);
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].identifier, "1:2:Mouse");
+        assert_eq!(observed_property(&resolved[0], "pointer_accel"), None);
+        assert_eq!(
+            observed_property(&resolved[0], "natural_scroll"),
+            Some(PropertyValue::Toggle(true))
+        );
+        assert_eq!(
+            observed_property(&resolved[0], "left_handed"),
+            Some(PropertyValue::Toggle(false))
+        );
+    }
+
+    #[test]
+    fn unanimous_syncs_agreeing_devices() {
+        let devices = vec![
+            pointer("1:2:Mouse", 0.5, "disabled"),
+            pointer("3:4:Trackball", 0.5, "disabled"),
+        ];
+        let changed = vec![pointer("3:4:Trackball", 0.5, "disabled")];
+        let resolved = BackSyncPolicy::Unanimous.resolve(changed, &devices);
+        assert_eq!(
+            observed_property(&resolved[0], "pointer_accel"),
+            Some(PropertyValue::Number(0.5))
+        );
+        assert_eq!(
+            observed_property(&resolved[0], "natural_scroll"),
+            Some(PropertyValue::Toggle(false))
+        );
+    }
+
+    #[test]
+    fn resolving_all_devices_matches_resolving_them_as_changed() {
+        for policy in [BackSyncPolicy::MostRecent, BackSyncPolicy::Unanimous] {
+            let devices = disagreeing_pointers();
+            let resolved = policy.resolve(disagreeing_pointers(), &devices);
+            let resolved_all = policy.resolve_all(disagreeing_pointers());
+            assert_eq!(resolved_all.len(), resolved.len());
+            for (all, changed) in resolved_all.iter().zip(&resolved) {
+                assert_eq!(all.identifier, changed.identifier);
+                for property in SYNCED_PROPERTIES {
+                    assert_eq!(
+                        observed_property(all, property),
+                        observed_property(changed, property),
+                        "{policy} {property}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn back_sync_policy_nicks_round_trip() {
+        for policy in [BackSyncPolicy::MostRecent, BackSyncPolicy::Unanimous] {
+            assert_eq!(BackSyncPolicy::from_nick(policy.nick()), Some(policy));
+        }
+        assert_eq!(BackSyncPolicy::from_nick("first"), None);
+    }
+
+    #[test]
+    fn fixed_precedence_ignores_times() {
+        let earlier = SystemTime::UNIX_EPOCH;
+        let later = earlier + Duration::from_secs(60);
+        for (gsettings, sway) in [
+            (None, None),
+            (Some(earlier), Some(later)),
+            (Some(later), None),
+        ] {
+            assert_eq!(
+                StartupPrecedence::GSettings.resolve(gsettings, sway),
+                Reconciliation::ApplyGSettings
+            );
+            assert_eq!(
+                StartupPrecedence::Sway.resolve(gsettings, sway),
+                Reconciliation::ImportSway
+            );
+        }
+    }
+
+    #[test]
+    fn newest_precedence_picks_the_later_change() {
+        let earlier = SystemTime::UNIX_EPOCH;
+        let later = earlier + Duration::from_secs(60);
+        let newest = StartupPrecedence::Newest;
+        assert_eq!(
+            newest.resolve(Some(earlier), Some(later)),
+            Reconciliation::ImportSway
+        );
+        assert_eq!(
+            newest.resolve(Some(later), Some(earlier)),
+            Reconciliation::ApplyGSettings
+        );
+        assert_eq!(
+            newest.resolve(Some(later), Some(later)),
+            Reconciliation::ApplyGSettings
+        );
+    }
+
+    #[test]
+    fn newest_precedence_applies_gsettings_without_times() {
+        let time = Some(SystemTime::UNIX_EPOCH);
+        let newest = StartupPrecedence::Newest;
+        assert_eq!(newest.resolve(None, time), Reconciliation::ApplyGSettings);
+        assert_eq!(newest.resolve(time, None), Reconciliation::ApplyGSettings);
+        assert_eq!(newest.resolve(None, None), Reconciliation::ApplyGSettings);
+    }
+
+    #[test]
+    fn precedence_nicks_round_trip() {
+        for precedence in [
+            StartupPrecedence::GSettings,
+            StartupPrecedence::Sway,
+            StartupPrecedence::Newest,
+        ] {
+            assert_eq!(
+                StartupPrecedence::from_nick(precedence.nick()),
+                Some(precedence)
+            );
+        }
+        assert_eq!(StartupPrecedence::from_nick("config"), None);
+    }
+}
@@ -0,0 +1,72 @@
+use log::info;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::batch;
+use crate::command::{InputCommand, InputTarget};
+
+const HEADER: &str =
+    "# Generated by regolith-inputd from gsettings, changes will be overwritten.\n";
+
+/// A sway config file of `input` blocks, so sway starts with the gsettings
+/// state before the daemon runs.
+pub struct ConfigSnippet {
+    path: PathBuf,
+}
+
+impl ConfigSnippet {
+    pub fn new(path: PathBuf) -> ConfigSnippet {
+        ConfigSnippet { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Groups `commands` into one block per target, in order.
+    pub fn render(commands: &[InputCommand]) -> String {
+        let mut targets: Vec<&InputTarget> = Vec::new();
+        for command in commands {
+            if !targets.contains(&command.target()) {
+                targets.push(command.target());
+            }
+        }
+        let mut snippet = String::from(HEADER);
+        for target in targets {
+            snippet.push_str(&format!("\ninput {target} {{\n"));
+            for command in commands.iter().filter(|c| c.target() == target) {
+                snippet.push_str(&format!("    {}\n", command.setting()));
+            }
+            snippet.push_str("}\n");
+        }
+        snippet
+    }
+
+    /// Replaces the file atomically with the rendered `commands`, unless it
+    /// already has that content. Returns whether it was written.
+    pub fn write(&self, commands: &[InputCommand]) -> io::Result<bool> {
+        let snippet = Self::render(commands);
+        if fs::read_to_string(&self.path).map_or(false, |current| current == snippet) {
+            return Ok(false);
+        }
+        if batch::is_dry_run() {
+            info!("Dry run, not writing {}:\n{snippet}", self.path.display());
+            return Ok(false);
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Hidden, so wildcard includes don't pick up a partial file
+        let mut name = std::ffi::OsString::from(".");
+        name.push(self.path.file_name().unwrap_or_default());
+        name.push(".tmp");
+        let temporary = self.path.with_file_name(name);
+        let mut file = fs::File::create(&temporary)?;
+        file.write_all(snippet.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temporary, &self.path)?;
+        info!("Wrote sway config snippet {}", self.path.display());
+        Ok(true)
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt::{self, Display};
+use swayipc::{EnabledOrDisabled, Input, ScrollMethod};
+
+use crate::traits::SwayTypeToPrimitive;
+
+/// Value of a sway input property, as set by a command and reported by
+/// `get_inputs`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PropertyValue {
+    Toggle(bool),
+    Number(f64),
+    Word(String),
+}
+
+/// A sway input property wanted by gsettings and the key it comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DesiredProperty {
+    pub key: &'static str,
+    pub property: &'static str,
+    pub value: PropertyValue,
+}
+
+/// Desired state of every device of a handler's input type.
+pub type DesiredState = Vec<DesiredProperty>;
+
+impl PropertyValue {
+    pub fn matches(&self, observed: &PropertyValue) -> bool {
+        match (self, observed) {
+            (PropertyValue::Number(a), PropertyValue::Number(b)) => (a - b).abs() < 1e-6,
+            (a, b) => a == b,
+        }
+    }
+
+    /// Reads a value as written in a sway command, the inverse of `Display`.
+    pub fn parse(value: &str) -> PropertyValue {
+        match value {
+            "enabled" => PropertyValue::Toggle(true),
+            "disabled" => PropertyValue::Toggle(false),
+            _ => match value.parse::<f64>() {
+                Ok(n) => PropertyValue::Number(n),
+                Err(_) => PropertyValue::Word(value.to_owned()),
+            },
+        }
+    }
+}
+
+impl Display for PropertyValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PropertyValue::Toggle(true) => f.write_str("enabled"),
+            PropertyValue::Toggle(false) => f.write_str("disabled"),
+            PropertyValue::Number(n) => write!(f, "{n}"),
+            PropertyValue::Word(w) => f.write_str(w),
+        }
+    }
+}
+
+impl DesiredProperty {
+    pub fn new(key: &'static str, property: &'static str, value: PropertyValue) -> Self {
+        DesiredProperty {
+            key,
+            property,
+            value,
+        }
+    }
+}
+
+fn toggle(value: &Option<EnabledOrDisabled>) -> Option<PropertyValue> {
+    value
+        .as_ref()
+        .map(|v| PropertyValue::Toggle(v.to_primitive()))
+}
+
+/// Value of `property` as reported by sway for `input`, if sway reports it.
+pub fn observed_property(input: &Input, property: &str) -> Option<PropertyValue> {
+    let libinput = input.libinput.as_ref()?;
+    match property {
+        "pointer_accel" => libinput.accel_speed.map(PropertyValue::Number),
+        "natural_scroll" => toggle(&libinput.natural_scroll),
+        "left_handed" => toggle(&libinput.left_handed),
+        "tap" => toggle(&libinput.tap),
+        "drag" => toggle(&libinput.tap_drag),
+        "drag_lock" => toggle(&libinput.tap_drag_lock),
+        "dwt" => toggle(&libinput.dwt),
+        "middle_emulation" => toggle(&libinput.middle_emulation),
+        "events" => libinput.send_events.as_ref().map(|events| {
+            let events: &str = events.to_primitive();
+            PropertyValue::Word(events.to_owned())
+        }),
+        "scroll_method" => libinput.scroll_method.as_ref().map(|method| {
+            PropertyValue::Word(String::from(match method {
+                ScrollMethod::TwoFinger => "two_finger",
+                ScrollMethod::Edge => "edge",
+                ScrollMethod::OnButtonDown => "on_button_down",
+                _ => "none",
+            }))
+        }),
+        _ => None,
+    }
+}
+
+/// Returns the desired properties that at least one device does not have
+/// yet. Properties sway doesn't report, and any property when no device is
+/// connected, are always returned so the type-wide config stays in place for
+/// devices plugged in later.
+pub fn diff<'a>(desired: &'a [DesiredProperty], devices: &[&Input]) -> Vec<&'a DesiredProperty> {
+    desired
+        .iter()
+        .filter(|prop| {
+            devices.is_empty()
+                || devices.iter().any(|device| {
+                    observed_property(device, prop.property)
+                        .map_or(true, |observed| !prop.value.matches(&observed))
+                })
+        })
+        .collect()
+}
@@ -0,0 +1,109 @@
+use gio::{prelude::SettingsExtManual, traits::SettingsExt, Settings};
+use log::{error, warn};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::batch::CommandResult;
+use crate::error::InputdError;
+use crate::utils;
+
+pub const STATUS_SCHEMA: &str = "org.regolith.inputd";
+
+/// Failures of the latest command sent for each gsettings key, published in
+/// the `failed-commands` key of the daemon's own schema.
+pub struct DaemonStatus {
+    settings: Option<Settings>,
+    failures: BTreeMap<String, String>,
+}
+
+impl DaemonStatus {
+    pub fn new() -> DaemonStatus {
+        let settings = utils::settings_if_installed(STATUS_SCHEMA);
+        if settings.is_none() {
+            warn!("Schema {STATUS_SCHEMA} is not installed, command failures won't be published");
+        }
+        DaemonStatus {
+            settings,
+            failures: BTreeMap::new(),
+        }
+    }
+
+    /// Logs the failed commands of `results` and publishes the failures of
+    /// every key, clearing the keys whose command succeeded this time.
+    pub fn record(&mut self, schema: &str, results: &[CommandResult]) {
+        let mut changed = false;
+        for result in results {
+            let id = format!("{schema} {}", result.key);
+            match &result.outcome {
+                Ok(()) => changed |= self.failures.remove(&id).is_some(),
+                Err(e) => {
+                    error!(
+                        "{id}={}: `{}` rejected by sway: {e}",
+                        result.value, result.command
+                    );
+                    let failure = format!("{id}={}: {e}", result.value);
+                    changed |= self.failures.insert(id, failure.clone()) != Some(failure);
+                }
+            }
+        }
+        if changed {
+            self.publish();
+        }
+    }
+
+    /// Publishes an error that kept any command from being sent for `key`.
+    pub fn record_error(&mut self, schema: &str, key: &str, error: &InputdError) {
+        let id = format!("{schema} {key}");
+        let failure = format!("{id}: {error}");
+        if self.failures.insert(id, failure.clone()) != Some(failure) {
+            self.publish();
+        }
+    }
+
+    pub fn clear_error(&mut self, schema: &str, key: &str) {
+        if self.failures.remove(&format!("{schema} {key}")).is_some() {
+            self.publish();
+        }
+    }
+
+    /// Time of the last gsettings change applied by the daemon, if any was
+    /// recorded.
+    pub fn gsettings_changed_at(&self) -> Option<SystemTime> {
+        let settings = self.settings.as_ref()?;
+        let secs: i64 = settings.get("gsettings-changed-at");
+        let secs = u64::try_from(secs).ok().filter(|secs| *secs > 0)?;
+        Some(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    pub fn record_gsettings_change(&self) {
+        let Some(settings) = &self.settings else {
+            return;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs() as i64);
+        if let Err(e) = settings.set("gsettings-changed-at", &now) {
+            error!("Failed to record the time of the change: {e}");
+        }
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &String> {
+        self.failures.values()
+    }
+
+    fn publish(&self) {
+        let Some(settings) = &self.settings else {
+            return;
+        };
+        let failures: Vec<&str> = self.failures().map(String::as_str).collect();
+        if let Err(e) = settings.set_strv("failed-commands", &failures) {
+            error!("Failed to publish command failures: {e}");
+        }
+    }
+}
+
+impl Default for DaemonStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
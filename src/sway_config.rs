@@ -0,0 +1,456 @@
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use swayipc::{Connection as SwayConnection, Input};
+
+use crate::error::Result;
+use crate::utils;
+
+/// Includes nested deeper than this are ignored, like sway does with loops.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Sway properties of an input type, with their arguments joined by spaces
+pub type ConfigProperties = HashMap<String, String>;
+
+/// An input property set by the user's sway config.
+#[derive(Debug, PartialEq, Eq)]
+pub struct InputSetting {
+    /// `*`, `type:<input type>` or a device identifier
+    pub target: String,
+    pub property: String,
+    pub args: String,
+}
+
+impl InputSetting {
+    /// Whether the setting applies to devices of `input_type`. Identifiers
+    /// are looked up in `devices`, so unplugged devices never match.
+    pub fn applies_to(&self, input_type: &str, devices: &[Input]) -> bool {
+        match self.target.strip_prefix("type:") {
+            _ if self.target == "*" => true,
+            Some(target_type) => target_type == input_type,
+            None => devices
+                .iter()
+                .any(|device| device.identifier == self.target && device.input_type == input_type),
+        }
+    }
+}
+
+/// The `input` settings of the config sway loaded, in config order. The
+/// included file at `skipped`, if any, isn't read.
+pub fn input_settings(
+    connection: &mut SwayConnection,
+    skipped: Option<&Path>,
+) -> Result<Vec<InputSetting>> {
+    Ok(parse_config(connection, skipped)?.settings)
+}
+
+/// Key combinations bound with `bindsym` in the default mode of the config
+/// sway loaded, with variables like `$mod` replaced.
+pub fn bound_keys(connection: &mut SwayConnection) -> Result<Vec<String>> {
+    Ok(parse_config(connection, None)?.bindings)
+}
+
+/// Last modification of the config sway loaded or of any file it
+/// includes, except for the one at `skipped`.
+pub fn last_modified(
+    connection: &mut SwayConnection,
+    skipped: Option<&Path>,
+) -> Option<SystemTime> {
+    let parser = parse_config(connection, skipped).ok()?;
+    parser
+        .files
+        .iter()
+        .filter_map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .max()
+}
+
+fn parse_config(connection: &mut SwayConnection, skipped: Option<&Path>) -> Result<ConfigParser> {
+    let config = connection.get_config()?.config;
+    let path = utils::sway_config_path();
+    let base = path
+        .as_ref()
+        .and_then(|path| path.parent().map(Path::to_path_buf))
+        .unwrap_or_default();
+    let mut parser = ConfigParser {
+        skipped: skipped.and_then(|path| fs::canonicalize(path).ok()),
+        files: path.into_iter().collect(),
+        ..ConfigParser::default()
+    };
+    parser.parse(&config, &base, 0);
+    Ok(parser)
+}
+
+/// Parses a sway boolean, which accepts more spellings than `enabled`.
+pub fn parse_toggle(arg: &str) -> Option<bool> {
+    match arg {
+        "1" | "yes" | "on" | "true" | "enable" | "enabled" => Some(true),
+        "0" | "no" | "off" | "false" | "disable" | "disabled" => Some(false),
+        _ => None,
+    }
+}
+
+/// Input properties set in the sway config. They are owned by the config:
+/// they are not applied from gsettings, only synced back for display.
+pub struct PinnedProperties {
+    pins: Vec<InputSetting>,
+}
+
+impl PinnedProperties {
+    pub fn new() -> PinnedProperties {
+        PinnedProperties { pins: Vec::new() }
+    }
+
+    /// Parses the config sway loaded, following its includes except for
+    /// our own generated `snippet`.
+    pub fn load(
+        connection: &mut SwayConnection,
+        snippet: Option<&Path>,
+    ) -> Result<PinnedProperties> {
+        let mut pins: Vec<InputSetting> = Vec::new();
+        for setting in input_settings(connection, snippet)? {
+            let known = pins
+                .iter()
+                .any(|pin| pin.target == setting.target && pin.property == setting.property);
+            if !known {
+                info!(
+                    "input {} {} is set in the sway config, leaving it to the config",
+                    setting.target, setting.property
+                );
+                pins.push(setting);
+            }
+        }
+        Ok(PinnedProperties { pins })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+
+    /// Whether the config sets `property` for any device of `input_type`.
+    pub fn pins(&self, input_type: &str, property: &str, devices: &[Input]) -> bool {
+        self.pins
+            .iter()
+            .filter(|pin| pin.property == property)
+            .any(|pin| pin.applies_to(input_type, devices))
+    }
+}
+
+impl Default for PinnedProperties {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Default)]
+struct ConfigParser {
+    variables: HashMap<String, String>,
+    settings: Vec<InputSetting>,
+    bindings: Vec<String>,
+    skipped: Option<PathBuf>,
+    /// The config and the includes read from it
+    files: Vec<PathBuf>,
+}
+
+enum Block {
+    Input(String),
+    Other,
+}
+
+impl ConfigParser {
+    fn parse(&mut self, config: &str, base: &Path, depth: usize) {
+        let mut blocks: Vec<Block> = Vec::new();
+        for line in logical_lines(config) {
+            let words = self.substitute(&split_words(&line));
+            let Some(first) = words.first() else {
+                continue;
+            };
+            if first == "}" {
+                blocks.pop();
+                continue;
+            }
+            let opens_block = words.last().map_or(false, |word| word == "{");
+            match (blocks.last(), first.as_str()) {
+                (Some(Block::Input(target)), _) => {
+                    let target = target.clone();
+                    if opens_block {
+                        blocks.push(Block::Other);
+                    }
+                    self.push_setting(target, &words);
+                }
+                (Some(Block::Other), _) if opens_block => blocks.push(Block::Other),
+                (Some(Block::Other), _) => {}
+                (None, "input") if opens_block && words.len() == 3 => {
+                    blocks.push(Block::Input(words[1].clone()));
+                }
+                (None, "input") if words.len() > 2 => {
+                    self.push_setting(words[1].clone(), &words[2..])
+                }
+                (None, "set") if words.len() > 2 => {
+                    self.variables
+                        .insert(words[1].clone(), words[2..].join(" "));
+                }
+                // Without a resource database, the fallback value is used
+                (None, "set_from_resource") if words.len() > 3 => {
+                    self.variables
+                        .insert(words[1].clone(), words[3..].join(" "));
+                }
+                (None, "bindsym") if !opens_block => {
+                    let combo = words[1..].iter().find(|word| !word.starts_with("--"));
+                    self.bindings.extend(combo.cloned());
+                }
+                (None, "include") if words.len() > 1 => {
+                    self.include(&words[1..].join(" "), base, depth)
+                }
+                (None, _) if opens_block => blocks.push(Block::Other),
+                (None, _) => {}
+            }
+        }
+    }
+
+    /// Records `words`, a property followed by its arguments, for `target`.
+    fn push_setting(&mut self, target: String, words: &[String]) {
+        let Some((property, args)) = words.split_first() else {
+            return;
+        };
+        self.settings.push(InputSetting {
+            target,
+            property: property.clone(),
+            args: args.join(" "),
+        });
+    }
+
+    fn include(&mut self, pattern: &str, base: &Path, depth: usize) {
+        if depth >= MAX_INCLUDE_DEPTH {
+            warn!("Not following include {pattern}, includes are nested too deep");
+            return;
+        }
+        for path in expand_include(pattern, base) {
+            if self.skipped.is_some() && fs::canonicalize(&path).ok() == self.skipped {
+                continue;
+            }
+            match fs::read_to_string(&path) {
+                Ok(config) => {
+                    let base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                    self.parse(&config, &base, depth + 1);
+                    self.files.push(path);
+                }
+                Err(e) => debug!("Skipping include {}: {e}", path.display()),
+            }
+        }
+    }
+
+    /// Replaces the `$variables` set so far, longest names first like sway.
+    fn substitute(&self, words: &[String]) -> Vec<String> {
+        let mut names: Vec<&String> = self.variables.keys().collect();
+        names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+        words
+            .iter()
+            .map(|word| {
+                names.iter().fold(word.clone(), |word, name| {
+                    word.replace(name.as_str(), &self.variables[*name])
+                })
+            })
+            .collect()
+    }
+}
+
+/// Joins lines continued with a trailing `\` and drops comments.
+fn logical_lines(config: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for line in config.lines() {
+        let line = line.trim();
+        if let Some(continued) = line.strip_suffix('\\') {
+            current.push_str(continued);
+            current.push(' ');
+            continue;
+        }
+        current.push_str(line);
+        let logical = std::mem::take(&mut current);
+        if !logical.starts_with('#') && !logical.is_empty() {
+            lines.push(logical);
+        }
+    }
+    lines
+}
+
+/// Splits a config line into words, honouring quotes.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (_, '\\') => word.extend(chars.next()),
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, c) if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            (_, c) => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Resolves an include the way sway's wordexp does for the common cases:
+/// `~`, environment variables, and `*` wildcards in the file name.
+fn expand_include(pattern: &str, base: &Path) -> Vec<PathBuf> {
+    let mut expanded = String::new();
+    let mut rest = pattern;
+    if let Some(stripped) = rest.strip_prefix('~') {
+        expanded.push_str(&env::var("HOME").unwrap_or_default());
+        rest = stripped;
+    }
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let name_part = &rest[start + 1..];
+        let (name, after) = match name_part.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => (braced, ""),
+            },
+            None => {
+                let end = name_part
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(name_part.len());
+                (&name_part[..end], &name_part[end..])
+            }
+        };
+        expanded.push_str(&env::var(name).unwrap_or_default());
+        rest = after;
+    }
+    expanded.push_str(rest);
+
+    let path = base.join(expanded);
+    let Some(file_pattern) = path.file_name().and_then(|name| name.to_str()) else {
+        return vec![path];
+    };
+    if !file_pattern.contains('*') {
+        return vec![path];
+    }
+    let file_pattern = file_pattern.to_owned();
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return vec![];
+    };
+    let mut matches: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name();
+            name.to_str()
+                .map_or(false, |name| wildcard_matches(&file_pattern, name))
+        })
+        .map(|entry| entry.path())
+        .collect();
+    matches.sort();
+    matches
+}
+
+fn wildcard_matches(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(config: &str) -> ConfigParser {
+        let mut parser = ConfigParser::default();
+        parser.parse(config, Path::new("/nonexistent"), 0);
+        parser
+    }
+
+    #[test]
+    fn bindings_of_the_default_mode_are_collected() {
+        let parser = parse(
+            "set_from_resource $mod wm.mod Mod4\n\
+             bindsym $mod+space exec launcher\n\
+             bindsym --to-code $mod+Shift+q kill\n\
+             mode \"resize\" {\n\
+                 bindsym Escape mode default\n\
+             }\n",
+        );
+        assert_eq!(parser.bindings, ["Mod4+space", "Mod4+Shift+q"]);
+    }
+
+    #[test]
+    fn input_blocks_and_lines_are_collected() {
+        let parser = parse(
+            "set $kb type:keyboard\n\
+             input $kb {\n\
+                 xkb_layout \"us,de\"\n\
+             }\n\
+             input type:touchpad tap enabled\n",
+        );
+        assert_eq!(
+            parser.settings,
+            [
+                InputSetting {
+                    target: String::from("type:keyboard"),
+                    property: String::from("xkb_layout"),
+                    args: String::from("us,de"),
+                },
+                InputSetting {
+                    target: String::from("type:touchpad"),
+                    property: String::from("tap"),
+                    args: String::from("enabled"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn includes_are_resolved_against_the_base_and_recorded() {
+        let base = env::temp_dir().join(format!("regolith-inputd-config-{}", std::process::id()));
+        fs::create_dir_all(base.join("config.d")).unwrap();
+        let included = base.join("config.d/50-touchpad.conf");
+        fs::write(&included, "input type:touchpad tap enabled\n").unwrap();
+        let skipped = base.join("config.d/90-inputd.conf");
+        fs::write(&skipped, "input type:touchpad tap disabled\n").unwrap();
+
+        let mut parser = ConfigParser {
+            skipped: fs::canonicalize(&skipped).ok(),
+            ..ConfigParser::default()
+        };
+        parser.parse("include config.d/*.conf\n", &base, 0);
+        fs::remove_dir_all(&base).unwrap();
+        assert_eq!(parser.files, [included]);
+        assert_eq!(parser.settings.len(), 1);
+        assert_eq!(parser.settings[0].args, "enabled");
+    }
+
+    #[test]
+    fn toggles_accept_sway_spellings() {
+        assert_eq!(parse_toggle("yes"), Some(true));
+        assert_eq!(parse_toggle("disable"), Some(false));
+        assert_eq!(parse_toggle("toggle"), None);
+    }
+}
@@ -0,0 +1,135 @@
+use glib::{Continue, IOCondition, SourceId};
+use log::warn;
+use serde::Deserialize;
+use std::env;
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use swayipc::{Event, Fallible};
+
+const MAGIC: &[u8; 6] = b"i3-ipc";
+const HEADER_LEN: usize = 14;
+const SUBSCRIBE: u32 = 2;
+
+#[derive(Deserialize)]
+struct SubscribeReply {
+    success: bool,
+}
+
+/// A sway event subscription read without blocking from the default main
+/// context, instead of from a thread iterating a blocking `EventStream`.
+pub struct SwayEventSource {
+    stream: UnixStream,
+    buffer: Vec<u8>,
+}
+
+impl SwayEventSource {
+    pub fn subscribe(events: &[&str]) -> io::Result<SwayEventSource> {
+        let path = env::var_os("SWAYSOCK")
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "SWAYSOCK is not set"))?;
+        let mut stream = UnixStream::connect(path)?;
+        let payload = serde_json::to_vec(events)?;
+        let mut message = Vec::with_capacity(HEADER_LEN + payload.len());
+        message.extend_from_slice(MAGIC);
+        message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&SUBSCRIBE.to_ne_bytes());
+        message.extend_from_slice(&payload);
+        stream.write_all(&message)?;
+
+        let mut header = [0; HEADER_LEN];
+        stream.read_exact(&mut header)?;
+        let mut reply = vec![0; payload_len(&header)];
+        stream.read_exact(&mut reply)?;
+        let reply: SubscribeReply = serde_json::from_slice(&reply)?;
+        if !reply.success {
+            return Err(io::Error::new(
+                ErrorKind::Other,
+                "sway rejected the subscription",
+            ));
+        }
+
+        stream.set_nonblocking(true)?;
+        Ok(SwayEventSource {
+            stream,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Calls `on_event` for every event sway sends and `on_close` once the
+    /// connection is gone, both from the default main context.
+    pub fn attach<F, C>(mut self, mut on_event: F, on_close: C) -> SourceId
+    where
+        F: FnMut(Fallible<Event>) + 'static,
+        C: FnOnce() + 'static,
+    {
+        let fd = self.stream.as_raw_fd();
+        let mut on_close = Some(on_close);
+        let condition = IOCondition::IN | IOCondition::HUP | IOCondition::ERR;
+        glib::unix_fd_add_local(fd, condition, move |_, _| {
+            let open = match self.read_available() {
+                Ok(open) => open,
+                Err(e) => {
+                    warn!("Failed to read sway events: {e}");
+                    false
+                }
+            };
+            while let Some(frame) = self.next_frame() {
+                match frame {
+                    Ok(frame) => on_event(Event::decode(frame)),
+                    Err(e) => {
+                        warn!("{e}");
+                        self.buffer.clear();
+                        break;
+                    }
+                }
+            }
+            if open {
+                return Continue(true);
+            }
+            if let Some(on_close) = on_close.take() {
+                on_close();
+            }
+            Continue(false)
+        })
+    }
+
+    /// Reads everything available, returning false once sway closed the
+    /// connection.
+    fn read_available(&mut self) -> io::Result<bool> {
+        let mut chunk = [0; 4096];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(true),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Splits the next complete message off the buffer.
+    fn next_frame(&mut self) -> Option<io::Result<(u32, Vec<u8>)>> {
+        if self.buffer.len() < HEADER_LEN {
+            return None;
+        }
+        if &self.buffer[..MAGIC.len()] != MAGIC {
+            return Some(Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Lost track of sway's message boundaries",
+            )));
+        }
+        let len = payload_len(&self.buffer[..HEADER_LEN]);
+        if self.buffer.len() < HEADER_LEN + len {
+            return None;
+        }
+        let event_type = u32::from_ne_bytes(self.buffer[10..HEADER_LEN].try_into().unwrap());
+        let payload = self.buffer[HEADER_LEN..HEADER_LEN + len].to_vec();
+        self.buffer.drain(..HEADER_LEN + len);
+        Some(Ok((event_type, payload)))
+    }
+}
+
+fn payload_len(header: &[u8]) -> usize {
+    u32::from_ne_bytes(header[6..10].try_into().unwrap()) as usize
+}
@@ -0,0 +1,126 @@
+use gio::{Cancellable, DBusCallFlags, DBusConnection, DBusConnectionFlags};
+use glib::{Continue, MainContext, MainLoop, ToVariant, VariantTy};
+use std::ffi::CStr;
+use std::sync::{mpsc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Tests on a bus dispatch on the default main context, so only one of them
+/// runs at a time.
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// A private dbus-daemon, stopped when dropped. The test owns the default
+/// main context meanwhile.
+pub struct TestBus {
+    bus: *mut gio::ffi::GTestDBus,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl TestBus {
+    pub fn up() -> TestBus {
+        let lock = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let bus = unsafe {
+            glib::ffi::g_main_context_acquire(glib::ffi::g_main_context_default());
+            let bus = gio::ffi::g_test_dbus_new(gio::ffi::G_TEST_DBUS_NONE);
+            gio::ffi::g_test_dbus_up(bus);
+            bus
+        };
+        TestBus { bus, _lock: lock }
+    }
+
+    fn address(&self) -> String {
+        let address = unsafe { CStr::from_ptr(gio::ffi::g_test_dbus_get_bus_address(self.bus)) };
+        address.to_string_lossy().into_owned()
+    }
+
+    /// A new connection to the bus, like a separate process would have.
+    pub fn connect(&self) -> DBusConnection {
+        connect(&self.address())
+    }
+
+    /// Connects and takes the well-known `name`.
+    pub fn connect_as(&self, name: &str) -> DBusConnection {
+        connect_as(&self.address(), name)
+    }
+
+    /// Connects as `name` from a thread of its own, where `serve` sets up
+    /// the service. Clients can then block on calls to it.
+    pub fn spawn_service<F>(&self, name: &str, serve: F) -> DBusConnection
+    where
+        F: FnOnce(&DBusConnection) + Send + 'static,
+    {
+        let address = self.address();
+        let name = name.to_owned();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let context = MainContext::new();
+            let main_loop = MainLoop::new(Some(&context), false);
+            context
+                .with_thread_default(|| {
+                    let connection = connect_as(&address, &name);
+                    serve(&connection);
+                    let quit = main_loop.clone();
+                    connection.connect_closed(move |_, _, _| quit.quit());
+                    sender.send(connection).expect("Test is waiting");
+                    main_loop.run();
+                })
+                .expect("New main context is free");
+        });
+        receiver.recv().expect("Service connected")
+    }
+
+    /// Dispatches the default main context until `done` holds, failing the
+    /// test after a few seconds.
+    pub fn run_until(&self, mut done: impl FnMut() -> bool) {
+        let context = MainContext::default();
+        // Wakes up iterations blocked on nothing else
+        let tick = glib::timeout_add_local(Duration::from_millis(20), || Continue(true));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() {
+            assert!(
+                Instant::now() < deadline,
+                "Timed out waiting on the test bus"
+            );
+            context.iteration(true);
+        }
+        tick.remove();
+    }
+}
+
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        unsafe {
+            gio::ffi::g_test_dbus_down(self.bus);
+            glib::gobject_ffi::g_object_unref(self.bus as *mut _);
+            glib::ffi::g_main_context_release(glib::ffi::g_main_context_default());
+        }
+    }
+}
+
+fn connect(address: &str) -> DBusConnection {
+    DBusConnection::for_address_sync(
+        address,
+        DBusConnectionFlags::AUTHENTICATION_CLIENT | DBusConnectionFlags::MESSAGE_BUS_CONNECTION,
+        None,
+        Cancellable::NONE,
+    )
+    .expect("Test bus is up")
+}
+
+fn connect_as(address: &str, name: &str) -> DBusConnection {
+    let connection = connect(address);
+    connection
+        .call_sync(
+            Some("org.freedesktop.DBus"),
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "RequestName",
+            Some(&(name, 4u32).to_variant()),
+            VariantTy::new("(u)").ok(),
+            DBusCallFlags::NONE,
+            -1,
+            Cancellable::NONE,
+        )
+        .expect("Name is free on the test bus");
+    connection
+}
@@ -0,0 +1,134 @@
+use glib::{ToVariant, Variant, VariantTy};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::error::{InputdError, Result};
+
+/// Highest version of the tick protocol this daemon understands. Payloads
+/// without a `version` are read as version 1.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// A command sent with `swaymsg -t send_tick '{"inputd": ...}'`.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "inputd", rename_all = "snake_case")]
+pub enum TickCommand {
+    Reapply,
+    NextLayout,
+    PreviousLayout,
+    ToggleTouchpad,
+    Set {
+        schema: String,
+        key: String,
+        value: Value,
+    },
+}
+
+/// What a tick payload asks of the daemon.
+#[derive(Debug, PartialEq)]
+pub enum TickPayload {
+    Command(TickCommand),
+    /// Not meant for the daemon, like the ticks of other tools
+    Foreign,
+}
+
+impl TickPayload {
+    /// Validates a payload carrying an `inputd` command against the
+    /// protocol. Payloads without one are left to other readers.
+    pub fn parse(payload: &str) -> Result<TickPayload> {
+        let Ok(value) = serde_json::from_str::<Value>(payload) else {
+            return Ok(TickPayload::Foreign);
+        };
+        if value.get("inputd").is_none() {
+            return Ok(TickPayload::Foreign);
+        }
+        let version = match value.get("version") {
+            None => 1,
+            Some(version) => version.as_u64().unwrap_or(0),
+        };
+        if version == 0 || version > u64::from(PROTOCOL_VERSION) {
+            return Err(InputdError::Unsupported(format!(
+                "Tick protocol version {}, only up to {PROTOCOL_VERSION} is understood",
+                value["version"]
+            )));
+        }
+        let command: TickCommand = serde_json::from_value(value)
+            .map_err(|e| InputdError::Validation(format!("Invalid tick {payload}: {e}")))?;
+        Ok(TickPayload::Command(command))
+    }
+}
+
+/// Converts the JSON `value` of a `set` command to the type of the key.
+/// Strings are taken as is for string keys, and parsed as GVariant text
+/// otherwise, which covers types JSON can't express like `a(ss)`.
+pub fn json_to_variant(value: &Value, expected: &VariantTy) -> Result<Variant> {
+    let parsed = match value {
+        Value::String(text) if expected.as_str() == "s" => Ok(text.to_variant()),
+        Value::String(text) => Variant::parse(Some(expected), text),
+        value => Variant::parse(Some(expected), &value.to_string()),
+    };
+    parsed.map_err(|e| {
+        InputdError::Validation(format!("Invalid value {value} for type {expected}: {e}"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads_without_version_are_version_1() {
+        let reapply = TickPayload::Command(TickCommand::Reapply);
+        assert_eq!(
+            TickPayload::parse(r#"{"inputd": "reapply"}"#).unwrap(),
+            reapply
+        );
+        let versioned = r#"{"inputd": "reapply", "version": 1}"#;
+        assert_eq!(TickPayload::parse(versioned).unwrap(), reapply);
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        for payload in [
+            r#"{"inputd": "reapply", "version": 2}"#,
+            r#"{"inputd": "reapply", "version": 0}"#,
+            r#"{"inputd": "reapply", "version": "1"}"#,
+        ] {
+            let parsed = TickPayload::parse(payload);
+            assert!(
+                matches!(parsed, Err(InputdError::Unsupported(_))),
+                "{payload}"
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_payloads_are_rejected_or_left_alone() {
+        // Not JSON, or no command for us: another tool's tick
+        for payload in ["", "inputd reapply", r#"{"i3bar": "refresh"}"#] {
+            assert_eq!(TickPayload::parse(payload).unwrap(), TickPayload::Foreign);
+        }
+        let missing_value = r#"{"inputd": "set", "schema": "s", "key": "k"}"#;
+        let parsed = TickPayload::parse(missing_value);
+        assert!(matches!(parsed, Err(InputdError::Validation(_))));
+    }
+
+    #[test]
+    fn unknown_commands_are_rejected() {
+        let parsed = TickPayload::parse(r#"{"inputd": "reboot"}"#);
+        assert!(matches!(parsed, Err(InputdError::Validation(_))));
+    }
+
+    #[test]
+    fn set_commands_carry_their_value() {
+        let payload = r#"{"inputd": "set", "schema": "s", "key": "k", "value": 0.5}"#;
+        let expected = TickCommand::Set {
+            schema: String::from("s"),
+            key: String::from("k"),
+            value: Value::from(0.5),
+        };
+        assert_eq!(
+            TickPayload::parse(payload).unwrap(),
+            TickPayload::Command(expected)
+        );
+    }
+}
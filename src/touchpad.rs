@@ -1,36 +1,45 @@
-use crate::traits::{InputHandler, PointerMethods, PrimitiveToSwayType, SwayTypeToPrimitive};
-use gio::{prelude::SettingsExtManual, traits::SettingsExt, Settings};
-use log::info;
+use crate::error::Result;
+use crate::state::{DesiredProperty, DesiredState, PropertyValue};
+use crate::sway_config::{self, ConfigProperties};
+use crate::traits::{Dependency, InputHandler, PointerMethods, SwayTypeToPrimitive};
+use crate::utils::{self, set_if_changed};
+use gio::{prelude::SettingsExtManual, Settings};
+use glib::{ToVariant, Variant};
 
-use std::error::Error;
 use swayipc::{Connection as SwayConnection, Input};
 
+const MOUSE_SCHEMA: &str = "org.gnome.desktop.peripherals.mouse";
+
+/// "left-handed" set to "mouse" follows the mouse's setting
+const DEPENDENCIES: &[Dependency] = &[Dependency {
+    schema: MOUSE_SCHEMA,
+    key: "left-handed",
+    dependent_key: "left-handed",
+}];
+
 pub struct TouchpadHandler {
     settings: Settings,
+    mouse_settings: Option<Settings>,
     sway_connection: SwayConnection,
 }
 impl TouchpadHandler {
-    pub fn new() -> TouchpadHandler {
-        let settings = Settings::new("org.gnome.desktop.peripherals.touchpad");
-        let connection = SwayConnection::new().unwrap();
-        TouchpadHandler {
+    pub fn new() -> Result<TouchpadHandler> {
+        let settings = utils::installed_settings("org.gnome.desktop.peripherals.touchpad")?;
+        let connection = SwayConnection::new()?;
+        Ok(TouchpadHandler {
             settings,
+            mouse_settings: utils::settings_if_installed(MOUSE_SCHEMA),
             sway_connection: connection,
-        }
+        })
     }
-    fn apply_tap(&mut self) -> Result<(), Box<dyn Error>> {
-        let tap_enabled: &str = self
-            .settings
-            .get::<bool>("tap-to-click")
-            .to_sway_type()
-            .to_primitive();
-        let cmd = format!("input type:touchpad tap {tap_enabled}");
-        self.sway_connection.run_command(cmd)?;
-        Ok(())
+    fn toggle(&self, key: &'static str, property: &'static str) -> DesiredProperty {
+        let enabled: bool = self.settings.get(key);
+        DesiredProperty::new(key, property, PropertyValue::Toggle(enabled))
     }
-    fn apply_scroll_method(&mut self) -> Result<(), Box<dyn Error>> {
-        let two_finger: bool = self.settings().get("two-finger-scrolling-enabled");
-        let edge_scroll: bool = self.settings().get("edge-scrolling-enabled");
+    fn scroll_method(&self, key: &'static str) -> DesiredProperty {
+        let enabled = |key: &str| self.has_key(key) && self.settings().get::<bool>(key);
+        let two_finger = enabled("two-finger-scrolling-enabled");
+        let edge_scroll = enabled("edge-scrolling-enabled");
         let scroll_method = if two_finger {
             "two_finger"
         } else if edge_scroll {
@@ -38,107 +47,80 @@ impl TouchpadHandler {
         } else {
             "none"
         };
-        let cmd = format!("input type:touchpad scroll_method {scroll_method}");
-        self.sway_connection.run_command(cmd)?;
-        Ok(())
+        DesiredProperty::new(
+            key,
+            "scroll_method",
+            PropertyValue::Word(scroll_method.to_owned()),
+        )
     }
-    fn use_dwt(&mut self) -> Result<(), Box<dyn Error>> {
-        let dwt_enabled: &str = self
-            .settings
-            .get::<bool>("disable-while-typing")
-            .to_sway_type()
-            .to_primitive();
-        let cmd = format!("input type:touchpad dwt {dwt_enabled}");
-        self.sway_connection.run_command(cmd)?;
-        Ok(())
-    }
-    fn send_events(&mut self) -> Result<(), Box<dyn Error>> {
+    fn send_events(&self) -> DesiredProperty {
         let new_val: String = self.settings().get("send-events");
-        let cmd = format!("input type:touchpad events {new_val}");
-        info!("{cmd}");
-        self.sway_connection.run_command(cmd)?;
-        Ok(())
-    }
-    fn use_drag(&mut self) -> Result<(), Box<dyn Error>> {
-        let use_drag_enabled: &str = self
-            .settings
-            .get::<bool>("tap-and-drag")
-            .to_sway_type()
-            .to_primitive();
-        let cmd = format!("input type:touchpad drag {use_drag_enabled}");
-        info!("{cmd}");
-        self.sway_connection.run_command(cmd)?;
-        Ok(())
-    }
-    fn use_drag_lock(&mut self) -> Result<(), Box<dyn Error>> {
-        let drag_lock_enabled: &str = self
-            .settings
-            .get::<bool>("tap-and-drag-lock")
-            .to_sway_type()
-            .to_primitive();
-        let cmd = format!("input type:touchpad drag_lock {drag_lock_enabled}");
-        info!("{cmd}");
-        self.sway_connection.run_command(cmd)?;
-        Ok(())
-    }
-
-    fn emulate_middle_click(&mut self) -> Result<(), Box<dyn Error>> {
-        let emulate_middle_enabled: &str = self
-            .settings
-            .get::<bool>("middle-click-emulation")
-            .to_sway_type()
-            .to_primitive();
-        let cmd = format!("input type:touchpad middle_emulation {emulate_middle_enabled}");
-        self.sway_connection.run_command(cmd)?;
-        Ok(())
+        // GSettings nicks use dashes where sway uses underscores
+        DesiredProperty::new(
+            "send-events",
+            "events",
+            PropertyValue::Word(new_val.replace('-', "_")),
+        )
     }
 }
 
 impl PointerMethods for TouchpadHandler {
-    fn pointer_type(&self) -> &str {
-        "touchpad"
-    }
-    fn apply_left_handed(&mut self) -> Result<(), Box<dyn Error>> {
+    fn left_handed(&self) -> DesiredProperty {
         let left_handed: String = self.settings().get("left-handed");
 
-        let left_handed_enabled: &str = match left_handed.as_ref() {
+        let left_handed_enabled: bool = match left_handed.as_ref() {
             "left" => true,
             "right" => false,
-            "mouse" => {
-                let mouse_settings = Settings::new("org.gnome.desktop.peripherals.mouse");
-                mouse_settings.get("left-handed")
-            }
+            "mouse" => self
+                .mouse_settings
+                .as_ref()
+                .filter(|mouse_settings| utils::has_key(mouse_settings, "left-handed"))
+                .map_or(false, |mouse_settings| mouse_settings.get("left-handed")),
             _ => false,
-        }
-        .to_sway_type()
-        .to_primitive();
-        let pointer_type = self.pointer_type();
-        let cmd = format!("input type:{pointer_type} left_handed {left_handed_enabled}");
-        info!("{cmd}");
-        self.sway_connection().run_command(cmd)?;
-        Ok(())
+        };
+        DesiredProperty::new(
+            "left-handed",
+            "left_handed",
+            PropertyValue::Toggle(left_handed_enabled),
+        )
     }
 }
 
 impl InputHandler for TouchpadHandler {
-    fn apply_changes(&mut self, key: &str) -> Result<(), Box<dyn Error>> {
-        info!("org.gnome.desktop.peripherals.touchpad -> Key: {key} chaged");
-        match key {
-            "speed" => self.apply_speed()?,
-            "natural-scroll" => self.apply_natural_scroll()?,
-            "tap-to-click" => self.apply_tap()?,
-            "two-finger-scrolling-enabled" | "edge-scrolling-enabled" => {
-                self.apply_scroll_method()?
-            }
-            "left-handed" => self.apply_left_handed()?,
-            "send-events" => self.send_events()?,
-            "disable-while-typing" => self.use_dwt()?,
-            "tap-and-drag" => self.use_drag()?,
-            "tap-and-drag-lock" => self.use_drag_lock()?,
-            "middle-click-emulation" => self.emulate_middle_click()?,
-            _ => (),
+    fn input_type(&self) -> &str {
+        "touchpad"
+    }
+    fn dependencies(&self) -> &[Dependency] {
+        DEPENDENCIES
+    }
+    fn keys(&self) -> &[&'static str] {
+        &[
+            "speed",
+            "left-handed",
+            "natural-scroll",
+            "tap-to-click",
+            "two-finger-scrolling-enabled",
+            "edge-scrolling-enabled",
+            "send-events",
+            "disable-while-typing",
+            "tap-and-drag",
+            "tap-and-drag-lock",
+            "middle-click-emulation",
+        ]
+    }
+    fn desired_properties(&self, key: &str) -> Result<DesiredState> {
+        let prop = match key {
+            "tap-to-click" => self.toggle("tap-to-click", "tap"),
+            "two-finger-scrolling-enabled" => self.scroll_method("two-finger-scrolling-enabled"),
+            "edge-scrolling-enabled" => self.scroll_method("edge-scrolling-enabled"),
+            "send-events" => self.send_events(),
+            "disable-while-typing" => self.toggle("disable-while-typing", "dwt"),
+            "tap-and-drag" => self.toggle("tap-and-drag", "drag"),
+            "tap-and-drag-lock" => self.toggle("tap-and-drag-lock", "drag_lock"),
+            "middle-click-emulation" => self.toggle("middle-click-emulation", "middle_emulation"),
+            _ => return Ok(self.pointer_properties(key).into_iter().collect()),
         };
-        Ok(())
+        Ok(vec![prop])
     }
     fn settings(&self) -> &Settings {
         &self.settings
@@ -146,43 +128,64 @@ impl InputHandler for TouchpadHandler {
     fn sway_connection(&mut self) -> &mut swayipc::Connection {
         &mut self.sway_connection
     }
-    fn apply_all(&mut self) -> Result<(), Box<dyn Error>> {
-        self.apply_speed()?;
-        self.apply_left_handed()?;
-        self.apply_natural_scroll()?;
-        self.apply_tap()?;
-        self.apply_scroll_method()?;
-        self.send_events()?;
-        self.use_dwt()?;
-        self.use_drag()?;
-        self.use_drag_lock()?;
-        self.emulate_middle_click()?;
-        Ok(())
-    }
-    fn sync_gsettings(&mut self, input: &Input) -> Result<(), Box<dyn Error>> {
+    fn sync_gsettings(&mut self, input: &Input) -> Result<()> {
         self.sync_pointer_gsettings(input)?;
         if input.libinput.is_none() {
             return Ok(());
         }
         let libinput = input.libinput.as_ref().unwrap();
         if let Some(enabled) = libinput.send_events.as_ref() {
-            self.settings()
-                .set_string("send-events", enabled.to_primitive())?;
+            let enabled: &str = enabled.to_primitive();
+            set_if_changed(self.settings(), "send-events", enabled.replace('_', "-"))?;
         }
         if let Some(tap) = &libinput.tap {
-            self.settings()
-                .set_boolean("tap-to-click", tap.to_primitive())?;
+            let tap: bool = tap.to_primitive();
+            set_if_changed(self.settings(), "tap-to-click", tap)?;
         }
         if let Some(drag) = &libinput.tap_drag {
-            self.settings()
-                .set_boolean("tap-and-drag", drag.to_primitive())?;
+            let drag: bool = drag.to_primitive();
+            set_if_changed(self.settings(), "tap-and-drag", drag)?;
         }
         if let Some(drag_lock) = &libinput.tap_drag_lock {
-            self.settings()
-                .set_boolean("tap-and-drag-lock", drag_lock.to_primitive())?;
+            let drag_lock: bool = drag_lock.to_primitive();
+            set_if_changed(self.settings(), "tap-and-drag-lock", drag_lock)?;
         }
         Ok(())
     }
+    fn imported_settings(&self, properties: &ConfigProperties) -> Vec<(&'static str, Variant)> {
+        let mut imported = self.imported_pointer_settings(properties);
+        let toggle = |property: &str| {
+            properties
+                .get(property)
+                .and_then(|arg| sway_config::parse_toggle(arg))
+        };
+        for (key, property) in [
+            ("tap-to-click", "tap"),
+            ("disable-while-typing", "dwt"),
+            ("tap-and-drag", "drag"),
+            ("tap-and-drag-lock", "drag_lock"),
+            ("middle-click-emulation", "middle_emulation"),
+        ] {
+            if let Some(enabled) = toggle(property) {
+                imported.push((key, enabled.to_variant()));
+            }
+        }
+        if let Some(left_handed) = toggle("left_handed") {
+            let left_handed = if left_handed { "left" } else { "right" };
+            imported.push(("left-handed", left_handed.to_variant()));
+        }
+        if let Some(events) = properties.get("events") {
+            imported.push(("send-events", events.replace('_', "-").to_variant()));
+        }
+        if let Some(method) = properties.get("scroll_method") {
+            let (two_finger, edge) = match method.as_str() {
+                "two_finger" => (true, false),
+                "edge" => (false, true),
+                _ => (false, false),
+            };
+            imported.push(("two-finger-scrolling-enabled", two_finger.to_variant()));
+            imported.push(("edge-scrolling-enabled", edge.to_variant()));
+        }
+        imported
+    }
 }
-unsafe impl Send for TouchpadHandler {}
-unsafe impl Sync for TouchpadHandler {}
@@ -1,124 +1,217 @@
+use crate::error::{InputdError, Result};
 use gio::prelude::SettingsExtManual;
 use gio::{traits::SettingsExt, Settings};
-use log::error;
-use std::error::Error;
-use std::sync::atomic::Ordering;
-use std::thread;
-use std::time::Duration;
+use glib::{ToVariant, Variant};
+use log::{error, info};
 use swayipc::{Connection as SwayConnection, EnabledOrDisabled, Input, SendEvents};
 
-use crate::{ALLOW_GSETTINGS_APPLY, ALLOW_SWAYINPUT_APPLY};
+use crate::batch::{CommandBatch, CommandResult};
+use crate::command::{InputCommand, InputTarget};
+use crate::layout::ActiveSource;
+use crate::state::{self, DesiredProperty, DesiredState, PropertyValue};
+use crate::sway_config::{self, ConfigProperties};
+use crate::utils::{self, set_if_changed};
+use crate::Message;
+
+/// A key of another schema that one of a handler's keys is computed from.
+pub struct Dependency {
+    pub schema: &'static str,
+    pub key: &'static str,
+    pub dependent_key: &'static str,
+}
 
 pub trait InputHandler {
     fn settings(&self) -> &Settings;
     fn sway_connection(&mut self) -> &mut SwayConnection;
-    fn apply_changes(&mut self, _: &str) -> Result<(), Box<dyn Error>>;
-    fn apply_all(&mut self) -> Result<(), Box<dyn Error>>;
-    fn sync_gsettings(&mut self, _: &Input) -> Result<(), Box<dyn Error>>;
-
-    fn apply_changes_sync(&mut self, key: &str) -> Result<(), Box<dyn Error>> {
-        unsafe {
-            ALLOW_SWAYINPUT_APPLY.store(false, Ordering::Relaxed);
-        }
-        let result = self.apply_changes(key);
-
-        thread::sleep(Duration::from_millis(100));
+    /// Sway input type of the devices configured by this handler
+    fn input_type(&self) -> &str;
+    /// Keys of the handler's schema, in the order they are applied
+    fn keys(&self) -> &[&'static str];
+    /// Keys of other schemas whose changes have to re-apply one of ours
+    fn dependencies(&self) -> &[Dependency] {
+        &[]
+    }
+    /// Sway properties that depend on `key`, computed from gsettings
+    fn desired_properties(&self, _: &str) -> Result<DesiredState>;
+    fn sync_gsettings(&mut self, _: &Input) -> Result<()>;
+    /// Switches to the next input source, or the previous one if
+    /// `backward`, for handlers that have input sources
+    fn switch_source(&mut self, _backward: bool) -> Result<Vec<CommandResult>> {
+        Err(InputdError::Unsupported(String::from(
+            "Handler has no input sources",
+        )))
+    }
+    /// Switches to the input source at `index` of the handler's sources
+    fn select_source(&mut self, _index: u32) -> Result<Vec<CommandResult>> {
+        Err(InputdError::Unsupported(String::from(
+            "Handler has no input sources",
+        )))
+    }
+    /// Switches sway to the input source at `index` without recording it
+    /// in `current` or the most recently used sources
+    fn switch_layout(&mut self, _index: u32) -> Result<Vec<CommandResult>> {
+        Err(InputdError::Unsupported(String::from(
+            "Handler has no input sources",
+        )))
+    }
+    /// Index of the input source in use, if the handler has input sources
+    fn current_source(&self) -> Option<u32> {
+        None
+    }
+    /// Follows the input source of a keyboard, returning it when it changed
+    fn track_layout(&mut self, _: &Input) -> Option<ActiveSource> {
+        None
+    }
+    /// Values of the handler's keys for the sway `properties` of its input
+    /// type, the inverse of `desired_properties`
+    fn imported_settings(&self, _: &ConfigProperties) -> Vec<(&'static str, Variant)> {
+        vec![]
+    }
+    /// Fills keys the user never set from system-wide configuration, and
+    /// keeps it following ours. Only the daemon does this.
+    fn connect_system(&self) {}
 
-        unsafe {
-            let allow = ALLOW_SWAYINPUT_APPLY.load(Ordering::Relaxed) && true;
-            ALLOW_SWAYINPUT_APPLY.store(allow, Ordering::Relaxed);
-        }
-        result
+    /// Whether the installed schema has `key`. Keys differ between GNOME
+    /// releases, and missing ones are skipped instead of panicking in `get`.
+    fn has_key(&self, key: &str) -> bool {
+        utils::has_key(self.settings(), key)
     }
 
-    fn apply_all_sync(&mut self) -> Result<(), Box<dyn Error>> {
-        unsafe {
-            if !ALLOW_GSETTINGS_APPLY.load(Ordering::Relaxed) {
-                return Ok(());
+    fn desired_state(&self) -> Result<DesiredState> {
+        let mut desired = DesiredState::new();
+        for key in self.keys().iter().filter(|key| self.has_key(key)) {
+            for prop in self.desired_properties(key)? {
+                if !desired.iter().any(|p| p.property == prop.property) {
+                    desired.push(prop);
+                }
             }
-            ALLOW_SWAYINPUT_APPLY.store(false, Ordering::Relaxed);
         }
+        Ok(desired)
+    }
 
-        let result = self.apply_all();
-
-        thread::sleep(Duration::from_millis(100));
-
-        unsafe {
-            let allow = ALLOW_SWAYINPUT_APPLY.load(Ordering::Relaxed) && true;
-            ALLOW_SWAYINPUT_APPLY.store(allow, Ordering::Relaxed);
+    /// Sends commands only for the desired properties that differ from the
+    /// state reported by `get_inputs`, batched into a single round-trip.
+    fn reconcile(&mut self, desired: &[DesiredProperty]) -> Result<Vec<CommandResult>> {
+        let batch = self.pending_commands(desired)?;
+        if batch.is_empty() {
+            return Ok(vec![]);
         }
-        result
+        info!("Executing command: {}", batch.payload());
+        Ok(batch.run(self.sway_connection())?)
     }
 
-    fn sync_gsettings_sync(&mut self, _: &Input) -> Result<(), Box<dyn Error>> {
-        unsafe {
-            if !ALLOW_SWAYINPUT_APPLY.load(Ordering::Relaxed) {
-                return Ok(());
-            }
-            ALLOW_GSETTINGS_APPLY.store(false, Ordering::Relaxed);
+    /// Commands `reconcile` would send for `desired`.
+    fn pending_commands(&mut self, desired: &[DesiredProperty]) -> Result<CommandBatch> {
+        let mut batch = CommandBatch::new();
+        if desired.is_empty() {
+            return Ok(batch);
         }
-
-        let result = self.apply_all();
-
-        thread::sleep(Duration::from_millis(100));
-
-        unsafe {
-            let allow = ALLOW_GSETTINGS_APPLY.load(Ordering::Relaxed) && true;
-            ALLOW_GSETTINGS_APPLY.store(allow, Ordering::Relaxed);
+        let input_type = self.input_type().to_owned();
+        let inputs = self.sway_connection().get_inputs()?;
+        let devices: Vec<&Input> = inputs
+            .iter()
+            .filter(|input| input.input_type == input_type)
+            .collect();
+        for prop in state::diff(desired, &devices) {
+            let target = InputTarget::Type(input_type.clone());
+            let cmd = InputCommand::new(target, prop.property, &prop.value)?;
+            batch.push(prop.key, prop.value.to_string(), cmd.to_string());
         }
-        result
+        Ok(batch)
     }
 
-    fn monitor_gsettings_change(&mut self)
-    where
-        Self: 'static,
-    {
-        let ptr: *mut Self = self;
-        self.settings().connect_changed(None, move |_, key| unsafe {
-            if !ptr.is_null() {
-                if let Err(e) = (*ptr).apply_changes_sync(key) {
-                    error!("{e}");
-                };
+    /// Replaces the handler's sway connection after an IPC failure.
+    fn reconnect(&mut self) -> Result<()> {
+        *self.sway_connection() = SwayConnection::new()?;
+        Ok(())
+    }
+
+    /// Forwards `changed` signals of the handler's settings to the manager,
+    /// tagged with the handler's index.
+    fn monitor_gsettings_change(&self, index: usize, sender: glib::Sender<Message>) {
+        self.settings().connect_changed(None, move |_, key| {
+            if let Err(e) = sender.send(Message::GSettingsChanged(index, key.to_owned())) {
+                error!("{e}");
             }
         });
     }
+
+    /// Forwards changes of the keys the handler depends on as changes of
+    /// its own dependent keys. The returned settings have to be kept alive
+    /// for the signals to be emitted.
+    fn monitor_dependencies(&self, index: usize, sender: glib::Sender<Message>) -> Vec<Settings> {
+        let mut monitored = Vec::new();
+        for dependency in self.dependencies() {
+            let Some(settings) = utils::settings_if_installed(dependency.schema) else {
+                continue;
+            };
+            if !utils::has_key(&settings, dependency.key) {
+                continue;
+            }
+            let sender = sender.clone();
+            let dependent_key = dependency.dependent_key;
+            settings.connect_changed(Some(dependency.key), move |_, _| {
+                let message = Message::GSettingsChanged(index, dependent_key.to_owned());
+                if let Err(e) = sender.send(message) {
+                    error!("{e}");
+                }
+            });
+            monitored.push(settings);
+        }
+        monitored
+    }
 }
 
 pub trait PointerMethods: InputHandler {
-    fn pointer_type(&self) -> &str;
-    fn apply_left_handed(&mut self) -> Result<(), Box<dyn Error>>;
-    fn apply_speed(&mut self) -> Result<(), Box<dyn Error>> {
+    fn left_handed(&self) -> DesiredProperty;
+    fn speed(&self) -> DesiredProperty {
         let new_val: f64 = self.settings().get("speed");
-        let pointer_type = self.pointer_type();
-        let cmd = format!("input type:{pointer_type} pointer_accel {new_val}");
-        self.sway_connection().run_command(cmd)?;
-        Ok(())
+        DesiredProperty::new("speed", "pointer_accel", PropertyValue::Number(new_val))
     }
-    fn apply_natural_scroll(&mut self) -> Result<(), Box<dyn Error>> {
-        let new_val: &str = if self.settings().get("natural-scroll") {
-            "enabled"
-        } else {
-            "disabled"
-        };
-        let pointer_type = self.pointer_type();
-        let cmd = format!("input type:{pointer_type} natural_scroll {new_val}");
-        self.sway_connection().run_command(cmd)?;
-        Ok(())
+    fn natural_scroll(&self) -> DesiredProperty {
+        let new_val: bool = self.settings().get("natural-scroll");
+        DesiredProperty::new(
+            "natural-scroll",
+            "natural_scroll",
+            PropertyValue::Toggle(new_val),
+        )
+    }
+    fn pointer_properties(&self, key: &str) -> Option<DesiredProperty> {
+        match key {
+            "speed" => Some(self.speed()),
+            "natural-scroll" => Some(self.natural_scroll()),
+            "left-handed" => Some(self.left_handed()),
+            _ => None,
+        }
+    }
+    fn imported_pointer_settings(
+        &self,
+        properties: &ConfigProperties,
+    ) -> Vec<(&'static str, Variant)> {
+        let mut imported = Vec::new();
+        if let Some(speed) = properties.get("pointer_accel") {
+            if let Ok(speed) = speed.parse::<f64>() {
+                imported.push(("speed", speed.to_variant()));
+            }
+        }
+        if let Some(natural) = properties.get("natural_scroll") {
+            if let Some(natural) = sway_config::parse_toggle(natural) {
+                imported.push(("natural-scroll", natural.to_variant()));
+            }
+        }
+        imported
     }
-    fn sync_pointer_gsettings(&self, input: &Input) -> Result<(), Box<dyn Error>> {
+    fn sync_pointer_gsettings(&self, input: &Input) -> Result<()> {
         if input.libinput.is_none() {
             return Ok(());
         }
         let libinput = input.libinput.as_ref().unwrap();
         if let Some(speed) = libinput.accel_speed {
-            self.settings().set_double("speed", speed)?;
+            set_if_changed(self.settings(), "speed", speed)?;
         }
         if let Some(natural) = libinput.natural_scroll.as_ref() {
-            self.settings()
-                .set_boolean("natural-scroll", natural.to_primitive())?;
-        }
-        if let Some(accel) = libinput.accel_speed {
-            self.settings().set_double("speed", accel)?;
+            let natural: bool = natural.to_primitive();
+            set_if_changed(self.settings(), "natural-scroll", natural)?;
         }
         Ok(())
     }
@@ -128,10 +221,6 @@ pub trait SwayTypeToPrimitive<T> {
     fn to_primitive(&self) -> T;
 }
 
-pub trait PrimitiveToSwayType<T> {
-    fn to_sway_type(self) -> T;
-}
-
 impl SwayTypeToPrimitive<bool> for EnabledOrDisabled {
     fn to_primitive(&self) -> bool {
         match self {
@@ -150,16 +239,6 @@ impl SwayTypeToPrimitive<&str> for EnabledOrDisabled {
     }
 }
 
-impl PrimitiveToSwayType<EnabledOrDisabled> for bool {
-    fn to_sway_type(self) -> EnabledOrDisabled {
-        if self {
-            EnabledOrDisabled::Enabled
-        } else {
-            EnabledOrDisabled::Disabled
-        }
-    }
-}
-
 impl SwayTypeToPrimitive<bool> for SendEvents {
     fn to_primitive(&self) -> bool {
         matches!(self, SendEvents::Enabled)
@@ -170,6 +249,8 @@ impl SwayTypeToPrimitive<&str> for SendEvents {
     fn to_primitive(&self) -> &'static str {
         match self {
             SendEvents::Enabled => "enabled",
+            SendEvents::Disabled => "disabled",
+            SendEvents::DisabledOnExternalMouse => "disabled_on_external_mouse",
             _ => "disabled",
         }
     }
@@ -1,32 +1,29 @@
-use log::{error, info, warn};
-use std::{error::Error, fmt::Display, thread, time::Duration};
-use swayipc::{Connection as SwayConnection, EventStream, EventType, Fallible, Input};
+use gio::{traits::SettingsExt, Settings, SettingsSchemaSource};
+use glib::{BoolError, ToVariant, Variant};
+use log::{debug, error, info, warn};
+use std::path::PathBuf;
+use std::{env, fmt::Display, fs, thread, time::Duration};
+use swayipc::Input;
 
+use crate::error::{self, InputdError};
 use crate::HandlerList;
 
-pub fn sync_input_gsettings<'a>(
-    handlers_sref: &'a mut HandlerList,
-    input: &Input,
-) -> Result<(), Box<dyn Error + 'a>> {
-    let input_type = input.input_type.clone();
-    let handler_index = match input_type.as_ref() {
-        "pointer" => 0,
-        "keyboard" => 1,
-        "touchpad" => 2,
-        _ => return Err("Incompatible input type".into()),
-    };
+pub fn sync_input_gsettings(handlers: &mut HandlerList, input: &Input) -> error::Result<()> {
+    let mut matching = handlers
+        .iter_mut()
+        .filter(|handler| handler.input_type() == input.input_type)
+        .peekable();
+    if matching.peek().is_none() {
+        let reason = format!("Incompatible input type {}", input.input_type);
+        return Err(InputdError::Unsupported(reason));
+    }
     info!("Recieved Sway InputEvent for {}", input.input_type);
-    let mut handlers_lock = handlers_sref.lock()?;
-    handlers_lock[handler_index].sync_gsettings_sync(input)?;
+    for handler in matching {
+        handler.sync_gsettings(input)?;
+    }
     Ok(())
 }
 
-pub fn get_new_inputevent_stream() -> Fallible<EventStream> {
-    let connection = SwayConnection::new()?;
-    let subs = [EventType::Input, EventType::Tick];
-    connection.subscribe(subs)
-}
-
 pub fn retry_action<F, T, E>(action: F, max_retry: usize, duration_before_retry: Duration) -> T
 where
     F: Fn() -> Result<T, E>,
@@ -48,3 +45,164 @@ where
         }
     }
 }
+
+/// Writes `value` to `key` unless gsettings already holds it, so back-syncs
+/// don't emit `changed` signals for values that didn't change. Keys this
+/// GNOME version lacks and keys locked down in dconf are left alone.
+pub fn set_if_changed<T: ToVariant>(
+    settings: &Settings,
+    key: &str,
+    value: T,
+) -> Result<bool, BoolError> {
+    if !has_key(settings, key) {
+        return Ok(false);
+    }
+    if !settings.is_writable(key) {
+        debug!("{key} is not writable, not syncing it back");
+        return Ok(false);
+    }
+    let value = value.to_variant();
+    if same_value(&settings.value(key), &value) {
+        return Ok(false);
+    }
+    settings.set_value(key, &value)?;
+    Ok(true)
+}
+
+/// Compares gsettings values, allowing for rounding in doubles.
+pub fn same_value(a: &Variant, b: &Variant) -> bool {
+    match (a.get::<f64>(), b.get::<f64>()) {
+        (Some(a), Some(b)) => (a - b).abs() < 1e-6,
+        _ => a == b,
+    }
+}
+
+/// Opens `schema_id` if it is installed, instead of aborting like
+/// `Settings::new` does.
+pub fn installed_settings(schema_id: &str) -> error::Result<Settings> {
+    let schema = SettingsSchemaSource::default()
+        .and_then(|source| source.lookup(schema_id, true))
+        .ok_or_else(|| InputdError::Settings(format!("Schema {schema_id} is not installed")))?;
+    Ok(Settings::new_full(
+        &schema,
+        None::<&gio::SettingsBackend>,
+        None,
+    ))
+}
+
+pub fn settings_if_installed(schema_id: &str) -> Option<Settings> {
+    installed_settings(schema_id).ok()
+}
+
+pub fn has_key(settings: &Settings, key: &str) -> bool {
+    settings
+        .settings_schema()
+        .map_or(false, |schema| schema.has_key(key))
+}
+
+/// Whether `value` has the type of `key` and lies in its range, which
+/// `set_value` would otherwise abort on.
+pub fn in_range(settings: &Settings, key: &str, value: &Variant) -> bool {
+    settings.settings_schema().map_or(false, |schema| {
+        if !schema.has_key(key) {
+            return false;
+        }
+        let key = schema.key(key);
+        value.type_() == key.value_type() && key.range_check(value)
+    })
+}
+
+/// Path of the config the running sway loaded: the one it was started
+/// with, or else found the way sway looks for it without `-c`.
+pub fn sway_config_path() -> Option<PathBuf> {
+    started_sway_config().or_else(default_sway_config)
+}
+
+/// The config passed to the sway serving `$SWAYSOCK`, whose pid is part of
+/// the socket name as in `sway-ipc.<uid>.<pid>.sock`.
+fn started_sway_config() -> Option<PathBuf> {
+    let socket = PathBuf::from(env::var_os("SWAYSOCK")?);
+    let name = socket.file_name()?.to_str()?;
+    let pid: u32 = name
+        .strip_suffix(".sock")?
+        .rsplit('.')
+        .next()?
+        .parse()
+        .ok()?;
+    let process = PathBuf::from(format!("/proc/{pid}"));
+    let cmdline = fs::read_to_string(process.join("cmdline")).ok()?;
+    let args: Vec<&str> = cmdline.split('\0').collect();
+    let path = PathBuf::from(config_arg(&args)?);
+    if path.is_absolute() {
+        return Some(path);
+    }
+    fs::read_link(process.join("cwd"))
+        .ok()
+        .map(|cwd| cwd.join(path))
+}
+
+/// Value of the `-c`/`--config` option in sway's `args`.
+fn config_arg<'a>(args: &[&'a str]) -> Option<&'a str> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if *arg == "-c" || *arg == "--config" {
+            return args.next().copied();
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path);
+        }
+        if let Some(path) = arg.strip_prefix("-c").filter(|path| !path.is_empty()) {
+            return Some(path);
+        }
+    }
+    None
+}
+
+fn default_sway_config() -> Option<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".config")));
+    let candidates = [
+        home.as_ref().map(|home| home.join(".sway/config")),
+        config_home.as_ref().map(|dir| dir.join("sway/config")),
+        home.as_ref().map(|home| home.join(".i3/config")),
+        config_home.as_ref().map(|dir| dir.join("i3/config")),
+        Some(PathBuf::from("/etc/sway/config")),
+        Some(PathBuf::from("/etc/i3/config")),
+    ];
+    candidates.into_iter().flatten().find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_outside_of_the_schema_are_not_in_range() {
+        let schema = SettingsSchemaSource::default()
+            .and_then(|source| source.lookup("org.gnome.desktop.peripherals.touchpad", true));
+        let Some(schema) = schema else {
+            return;
+        };
+        let backend = gio::memory_settings_backend_new();
+        let settings = Settings::new_full(&schema, Some(&backend), None);
+        assert!(in_range(&settings, "send-events", &"disabled".to_variant()));
+        assert!(!in_range(&settings, "send-events", &"off".to_variant()));
+        assert!(in_range(&settings, "speed", &0.5.to_variant()));
+        assert!(!in_range(&settings, "speed", &2.0.to_variant()));
+        assert!(!in_range(&settings, "speed", &"fast".to_variant()));
+        assert!(!in_range(&settings, "no-such-key", &true.to_variant()));
+    }
+
+    #[test]
+    fn config_is_found_in_any_option_spelling() {
+        let config = Some("/etc/sway/kiosk");
+        assert_eq!(config_arg(&["sway", "-c", "/etc/sway/kiosk"]), config);
+        assert_eq!(config_arg(&["sway", "-d", "-c/etc/sway/kiosk"]), config);
+        assert_eq!(config_arg(&["sway", "--config", "/etc/sway/kiosk"]), config);
+        assert_eq!(config_arg(&["sway", "--config=/etc/sway/kiosk"]), config);
+        assert_eq!(config_arg(&["sway", "--unsupported-gpu", ""]), None);
+        assert_eq!(config_arg(&["sway", "-c"]), None);
+    }
+}